@@ -0,0 +1,32 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+/// Colors are only used when writing to a terminal, and never if `NO_COLOR` is set.
+fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        !std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+            && std::io::stdout().is_terminal()
+    })
+}
+
+fn paint(code: u8, text: impl Display) -> String {
+    if enabled() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub(crate) fn red(text: impl Display) -> String {
+    paint(31, text)
+}
+
+pub(crate) fn green(text: impl Display) -> String {
+    paint(32, text)
+}
+
+pub(crate) fn yellow(text: impl Display) -> String {
+    paint(33, text)
+}
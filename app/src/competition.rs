@@ -1,7 +1,8 @@
 use std::time::Duration;
 
-use clap::{Arg, ArgMatches, Command, value_parser};
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
 
+use v5_serial::brain::Brain;
 use v5_serial::brain::competition::CompetitionState;
 use v5_serial::connection::RobotConnectionOptions;
 use v5_serial::error::CommandError;
@@ -13,10 +14,27 @@ const DISABLE: &str = "disable";
 const AUTONOMOUS: &str = "autonomous";
 const OPCONTROL: &str = "opcontrol";
 const LENGTH: &str = "length";
+const NO_DISABLE: &str = "no-disable";
+const SKILLS: &str = "skills";
+const KIND: &str = "kind";
+const CUES: &str = "cues";
+
+/// When the warning cue is given before the end of operator control, like at events.
+const END_WARNING: Duration = Duration::from_secs(30);
+
+/// Both programming and driver skills runs last one minute.
+const SKILLS_LENGTH: Duration = Duration::from_secs(60);
 
 pub(crate) fn command() -> Command {
     Command::new(COMMAND)
         .about("Simulate a competition")
+        .arg(
+            Arg::new(CUES)
+                .long(CUES)
+                .help("Rings the terminal bell and prints a banner when a period starts, 30 seconds before the end of operator control and when a period ends")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
         .subcommand(Command::new(START).about("Starts an interactive competition manager"))
         .subcommand(
             Command::new(AUTONOMOUS)
@@ -26,6 +44,12 @@ pub(crate) fn command() -> Command {
                         .short('l')
                         .default_value("15000")
                         .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new(NO_DISABLE)
+                        .long(NO_DISABLE)
+                        .help("Leaves the robot in autonomous once the period is over")
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
@@ -38,6 +62,17 @@ pub(crate) fn command() -> Command {
                         .value_parser(value_parser!(u64)),
                 ),
         )
+        .subcommand(
+            Command::new(SKILLS)
+                .about("Runs a one minute skills run, then disables the robot")
+                .arg(
+                    Arg::new(KIND)
+                        .index(1)
+                        .required(true)
+                        .help("Programming skills runs autonomous, driver skills operator control")
+                        .value_parser(["programming", "driver"]),
+                ),
+        )
         .subcommand(Command::new(DISABLE).about("Disables the robot"))
 }
 
@@ -47,11 +82,13 @@ pub(crate) async fn competition(
     options: RobotConnectionOptions,
 ) -> Result<(), CommandError> {
     if let Some((command, args)) = args.subcommand() {
+        let mut brain = v5_serial::connection::connect_to_brain(options).await?;
         match command {
-            START => start(options, args).await,
-            AUTONOMOUS => autonomous(options, args).await,
-            OPCONTROL => opcontrol(options, args).await,
-            DISABLE => disable(options, args).await,
+            START => start(&mut brain, args).await,
+            AUTONOMOUS => autonomous(&mut brain, args).await,
+            OPCONTROL => opcontrol(&mut brain, args).await,
+            SKILLS => skills(&mut brain, args).await,
+            DISABLE => disable(&mut brain, args).await,
             _ => {
                 cmd.print_long_help().expect("failed to print help");
                 Err(CommandError::InvalidSubcommand)
@@ -63,39 +100,97 @@ pub(crate) async fn competition(
     }
 }
 
-async fn autonomous(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn autonomous(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let time = Duration::from_millis(*args.get_one::<u64>(LENGTH).expect("length"));
-    brain
-        .set_competition_state(CompetitionState::Autonomous, 0)
-        .await?;
-    tokio::time::sleep(time).await;
-    Ok(())
+    run_period(
+        brain,
+        CompetitionState::Autonomous,
+        time,
+        !args.get_flag(NO_DISABLE),
+        args.get_flag(CUES),
+    )
+    .await
 }
 
-async fn opcontrol(options: RobotConnectionOptions, args: &ArgMatches) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn opcontrol(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let time = Duration::from_millis(*args.get_one::<u64>(LENGTH).expect("length"));
-    brain
-        .set_competition_state(CompetitionState::OpControl, 0)
-        .await?;
-    tokio::time::sleep(time).await;
+    run_period(
+        brain,
+        CompetitionState::OpControl,
+        time,
+        true,
+        args.get_flag(CUES),
+    )
+    .await
+}
+
+async fn skills(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let state = match args.get_one::<String>(KIND).expect("kind").as_str() {
+        "programming" => CompetitionState::Autonomous,
+        _ => CompetitionState::OpControl,
+    };
+    run_period(brain, state, SKILLS_LENGTH, true, args.get_flag(CUES)).await
+}
+
+/// Puts the robot in `state` for `time`, then disables it if `disable` is set.
+/// The brain stays in whatever state it was last given, so without disabling the robot keeps
+/// running after the period. Interrupting the period with Ctrl+C always disables the robot.
+async fn run_period(
+    brain: &mut Brain,
+    state: CompetitionState,
+    time: Duration,
+    disable: bool,
+    cues: bool,
+) -> Result<(), CommandError> {
+    let name = match state {
+        CompetitionState::Autonomous => "Autonomous",
+        CompetitionState::OpControl => "Driver control",
+        _ => "Period",
+    };
+    brain.set_competition_state(state, 0).await?;
+    if cues {
+        cue(format!("{} started", name));
+    }
+    let interrupted = tokio::select! {
+        _ = async {
+            let warn = matches!(state, CompetitionState::OpControl) && time > END_WARNING;
+            if cues && warn {
+                tokio::time::sleep(time - END_WARNING).await;
+                cue(format!("{} seconds left", END_WARNING.as_secs()));
+                tokio::time::sleep(END_WARNING).await;
+            } else {
+                tokio::time::sleep(time).await;
+            }
+        } => false,
+        _ = tokio::signal::ctrl_c() => true,
+    };
+    if disable || interrupted {
+        brain
+            .set_competition_state(CompetitionState::Disabled, 0)
+            .await?;
+    }
+    if cues && !interrupted {
+        cue(format!("{} over", name));
+    }
     Ok(())
 }
 
-async fn disable(options: RobotConnectionOptions, _args: &ArgMatches) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+/// Rings the terminal bell and prints a banner, for drivers practicing without a field.
+fn cue(message: String) {
+    println!(
+        "\x07{}",
+        crate::color::yellow(format!("==== {} ====", message.to_uppercase()))
+    );
+}
+
+async fn disable(brain: &mut Brain, _args: &ArgMatches) -> Result<(), CommandError> {
     brain
         .set_competition_state(CompetitionState::Disabled, 0)
         .await?;
     Ok(())
 }
 
-async fn start(options: RobotConnectionOptions, _args: &ArgMatches) -> Result<(), CommandError> {
-    let _brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn start(_brain: &mut Brain, _args: &ArgMatches) -> Result<(), CommandError> {
     //todo
     Ok(())
 }
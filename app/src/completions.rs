@@ -0,0 +1,22 @@
+use clap::{Arg, ArgMatches, Command, value_parser};
+use clap_complete::Shell;
+
+pub(crate) const COMMAND: &str = "completions";
+const SHELL: &str = "shell";
+
+pub(crate) fn command() -> Command {
+    Command::new(COMMAND)
+        .about("Generates shell completions")
+        .arg(
+            Arg::new(SHELL)
+                .index(1)
+                .required(true)
+                .value_parser(value_parser!(Shell)),
+        )
+}
+
+pub(crate) fn completions(root: &mut Command, args: &ArgMatches) {
+    let shell = *args.get_one::<Shell>(SHELL).expect("shell");
+    let name = root.get_name().to_string();
+    clap_complete::generate(shell, root, name, &mut std::io::stdout());
+}
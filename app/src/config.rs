@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use ini::{Ini, Properties};
+use log::warn;
+
+const CONNECTION: &str = "connection";
+const LOG: &str = "log";
+
+/// Defaults for the global connection options, read from `<config dir>/v5_cli/config.ini`.
+/// Options passed on the command line always take precedence.
+#[derive(Default)]
+pub(crate) struct Config {
+    pub(crate) port: Option<String>,
+    pub(crate) baud_rate: Option<u32>,
+    pub(crate) bluetooth: bool,
+    pub(crate) mac_address: Option<String>,
+    pub(crate) pin: Option<String>,
+    pub(crate) daemon: bool,
+    pub(crate) daemon_port: Option<u16>,
+    pub(crate) timeout: Option<u64>,
+    pub(crate) timeout_upload: Option<u64>,
+    pub(crate) connect_timeout: Option<u64>,
+    pub(crate) verbose: bool,
+}
+
+impl Config {
+    pub(crate) fn load() -> Self {
+        let Some(path) = config_path() else {
+            return Self::default();
+        };
+        if !path.is_file() {
+            return Self::default();
+        }
+
+        let ini = match Ini::load_from_file(&path) {
+            Ok(ini) => ini,
+            Err(err) => {
+                warn!("failed to read config file {}: {}", path.display(), err);
+                return Self::default();
+            }
+        };
+
+        let empty = Properties::new();
+        let connection = ini.section(Some(CONNECTION)).unwrap_or(&empty);
+        let log = ini.section(Some(LOG)).unwrap_or(&empty);
+        Self {
+            port: connection.get("port").map(str::to_string),
+            baud_rate: parse(connection, "baud"),
+            bluetooth: parse(connection, "bluetooth").unwrap_or(false),
+            mac_address: connection.get("mac_address").map(str::to_string),
+            pin: connection.get("pin").map(str::to_string),
+            daemon: parse(connection, "daemon").unwrap_or(false),
+            daemon_port: parse(connection, "daemon_port"),
+            timeout: parse(connection, "timeout"),
+            timeout_upload: parse(connection, "timeout_upload"),
+            connect_timeout: parse(connection, "connect_timeout")
+                .or_else(|| parse(connection, "scan_timeout")),
+            verbose: parse(log, "verbose").unwrap_or(false),
+        }
+    }
+}
+
+fn parse<T: FromStr>(section: &Properties, key: &str) -> Option<T> {
+    let value = section.get(key)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("ignoring invalid config value `{}` for `{}`", value, key);
+            None
+        }
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+    Some(base.join("v5_cli").join("config.ini"))
+}
+
+/// Where to keep state between runs, such as which cold packages are known to be on the brain.
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    }?;
+    Some(base.join("v5_cli"))
+}
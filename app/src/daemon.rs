@@ -1,20 +1,27 @@
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 
-use clap::{Arg, ArgMatches, Command, value_parser};
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
 use log::{info, warn};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{Mutex, MutexGuard, Notify};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
 
 use v5_serial::connection::{RobotConnection, RobotConnectionOptions};
-use v5_serial::connection::daemon::DaemonCommand;
-use v5_serial::error::{CommandError, ConnectionError};
+use v5_serial::connection::daemon::{DaemonCommand, DAEMON_FULL};
+use v5_serial::error::{CommandError, CommunicationError, ConnectionError};
 
 pub(crate) const COMMAND: &str = "daemon";
 const DAEMON_PORT: &str = "daemon-port";
+const LIST: &str = "list";
+const MAX_CLIENTS: &str = "max-clients";
+
+/// Connected clients, and whether each one holds the exclusive claim.
+type Clients = Arc<std::sync::Mutex<HashMap<SocketAddr, bool>>>;
 
 pub(crate) fn command() -> Command {
     Command::new(COMMAND)
@@ -25,6 +32,19 @@ pub(crate) fn command() -> Command {
                 .value_parser(value_parser!(u16))
                 .index(1),
         )
+        .arg(
+            Arg::new(MAX_CLIENTS)
+                .long(MAX_CLIENTS)
+                .help("How many clients may be connected at once")
+                .default_value("16")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new(LIST)
+                .long(LIST)
+                .help("Lists the clients of a running daemon instead of starting one")
+                .action(ArgAction::SetTrue),
+        )
 }
 
 pub(crate) async fn daemon(
@@ -32,11 +52,14 @@ pub(crate) async fn daemon(
     args: ArgMatches,
     options: RobotConnectionOptions,
 ) -> Result<(), CommandError> {
-    let system_listener = TcpListener::bind(SocketAddr::new(
-        IpAddr::V4(Ipv4Addr::LOCALHOST),
-        *args.get_one(DAEMON_PORT).expect("port should exist"),
-    ))
-    .await?;
+    let port = *args.get_one(DAEMON_PORT).expect("port should exist");
+    let max_clients = *args.get_one::<u64>(MAX_CLIENTS).expect("max clients") as usize;
+    if args.get_flag(LIST) {
+        return list_clients(port).await;
+    }
+
+    let system_listener =
+        TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)).await?;
 
     let mut brain = v5_serial::connection::connect_to_brain(options).await?;
     let (tx, mut system_rx) = tokio::sync::mpsc::channel(1024);
@@ -53,42 +76,77 @@ pub(crate) async fn daemon(
 
     let error_handle = Arc::new(Notify::new());
     let eh3 = Arc::clone(&error_handle);
+    let clients: Clients = Arc::default();
 
     tokio::task::spawn(async move {
         loop {
             let (mut stream, address) = system_listener.accept().await.unwrap();
+            if clients.lock().unwrap().len() >= max_clients {
+                warn!(
+                    "rejecting {:?}, {} clients are already connected",
+                    address, max_clients
+                );
+                // the client may already be gone, which is fine as it is being rejected anyway
+                let _ = stream.write_u16(DAEMON_FULL).await;
+                continue;
+            }
             info!("new connection from {:?}", address);
             stream.write_u16(packet_size).await.unwrap();
-            tokio::task::spawn(connection_handler(
+            clients.lock().unwrap().insert(address, false);
+            let handler = connection_handler(
                 stream,
+                address,
+                Arc::clone(&clients),
                 Arc::clone(&system_handle),
                 Arc::clone(&user_handle),
                 sender_.subscribe(),
                 Arc::clone(&eh3),
-            ));
+            );
+            let clients = Arc::clone(&clients);
+            tokio::task::spawn(async move {
+                let result = handler.await;
+                clients.lock().unwrap().remove(&address);
+                info!("{:?} disconnected", address);
+                result
+            });
         }
     });
 
     let mut buf = [0_u8; 1024];
+    // cleared when the connection has no user port, so reading it doesn't spin the loop
+    let mut user_serial = true;
     loop {
         tokio::select! {
             t = system_rx.recv() => {
                 system_tx.send(brain.connection.send_packet(&t.unwrap()).await.unwrap().consume()).await.unwrap()
             }
             t = user_rx.recv() => {
-                brain.connection.write_serial(&t.unwrap()).await.unwrap();
+                match brain.connection.write_serial(&t.unwrap()).await {
+                    Err(CommunicationError::UserSerialUnavailable) => {}
+                    result => {
+                        result.unwrap();
+                    }
+                }
             }
-            t = brain.connection.read_serial(&mut buf) => {
-                if t.is_ok() {
-                    let l = t.unwrap();
-                    if l > 0 {
+            t = brain.connection.read_serial(&mut buf), if user_serial => {
+                match t {
+                    Ok(l) if l > 0 => {
                         user_tx.send(buf[..l].to_vec().into_boxed_slice()).unwrap();
                     }
+                    Err(CommunicationError::UserSerialUnavailable) => {
+                        warn!("no user port, user serial will not be forwarded");
+                        user_serial = false;
+                    }
+                    _ => {}
                 }
             }
             _ = error_handle.notified() => {
                 warn!("Attempting to reset connection");
-                brain.connection.reset().await.unwrap();
+                match brain.connection.reset().await {
+                    // the user port may have been reopened
+                    Ok(()) => user_serial = true,
+                    Err(err) => warn!("Failed to reset connection: {}", err),
+                }
             }
         }
     }
@@ -97,6 +155,8 @@ pub(crate) async fn daemon(
 
 async fn connection_handler(
     mut stream: TcpStream,
+    address: SocketAddr,
+    clients: Clients,
     system_handle: Arc<Mutex<(Sender<Box<[u8]>>, Receiver<Box<[u8]>>)>>,
     user_handle: Arc<Mutex<Sender<Box<[u8]>>>>,
     mut user_rx: tokio::sync::broadcast::Receiver<Box<[u8]>>,
@@ -126,6 +186,7 @@ async fn connection_handler(
 
                             let response = guard.1.recv().await.unwrap();
 
+                            stream.write_u8(DaemonCommand::SendSystem.into()).await?;
                             stream.write_u16(response.len() as u16).await?;
                             stream.write_all(&response).await?;
                             stream.flush().await?;
@@ -142,11 +203,28 @@ async fn connection_handler(
                         DaemonCommand::ClaimExclusive => {
                             assert!(exclusive.is_none());
                             exclusive = Some(system_handle.lock().await);
+                            clients.lock().unwrap().insert(address, true);
                         }
                         DaemonCommand::UnclaimExclusive => {
                             assert!(exclusive.is_some());
                             exclusive = None;
+                            clients.lock().unwrap().insert(address, false);
                         },
+                        DaemonCommand::ListClients => {
+                            let list: String = clients
+                                .lock()
+                                .unwrap()
+                                .iter()
+                                .filter(|(client, _)| **client != address)
+                                .map(|(client, exclusive)| {
+                                    let lock = if *exclusive { " (exclusive)" } else { "" };
+                                    format!("{}{}\n", client, lock)
+                                })
+                                .collect();
+                            stream.write_u8(DaemonCommand::ListClients.into()).await?;
+                            stream.write_u16(list.len() as u16).await?;
+                            stream.write_all(list.as_bytes()).await?;
+                        }
                         DaemonCommand::Reset => {
                             arc.notify_one();
                         }
@@ -160,7 +238,15 @@ async fn connection_handler(
                 }
             }
             b = user_rx.recv() => {
-                let buf = b.unwrap();
+                let buf = match b {
+                    Ok(buf) => buf,
+                    // output can burst after a reset, skip what was missed rather than dropping the client
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Client fell behind, skipped {} serial messages", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
                 stream.write_u8(DaemonCommand::SendUser as u8).await?;
                 stream.write_u16(buf.len() as u16).await?;
                 stream.write_all(&buf).await?;
@@ -168,3 +254,29 @@ async fn connection_handler(
         }
     }
 }
+
+/// Prints the clients of the daemon listening on `port`.
+async fn list_clients(port: u16) -> Result<(), CommandError> {
+    let mut stream =
+        TcpStream::connect(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)).await?;
+    if stream.read_u16().await? == DAEMON_FULL {
+        return Err(ConnectionError::DaemonFull.into());
+    }
+    stream.write_u8(DaemonCommand::ListClients.into()).await?;
+
+    // user serial output may be forwarded before the answer
+    loop {
+        let command = stream.read_u8().await?;
+        let mut buf = vec![0_u8; stream.read_u16().await? as usize];
+        stream.read_exact(&mut buf).await?;
+        if let Ok(DaemonCommand::ListClients) = DaemonCommand::try_from(command) {
+            let list = String::from_utf8_lossy(&buf);
+            if list.is_empty() {
+                println!("No clients are connected");
+            } else {
+                print!("{}", list);
+            }
+            return Ok(());
+        }
+    }
+}
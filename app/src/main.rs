@@ -1,24 +1,43 @@
+use std::time::Duration;
+
 use clap::{Arg, ArgAction, Command, value_parser};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::parser::ValueSource;
+use log::LevelFilter;
 
-use v5_serial::connection::RobotConnectionOptions;
+use v5_serial::brain::Brain;
+use v5_serial::brain::system::Product;
+use v5_serial::connection::{RobotConnectionOptions, Timeouts, ATT_MTU_RANGE, DEFAULT_ATT_MTU};
+use v5_serial::error::{CommandError, ConnectionError};
 
+mod color;
 mod competition;
+mod completions;
+mod config;
 mod daemon;
 mod manage;
+mod table;
 mod terminal;
 mod upload;
 
 const PORT: &str = "port";
+const BAUD: &str = "baud";
+const AUTO_RECONNECT: &str = "auto-reconnect";
+const CONTROLLER: &str = "controller";
+const NO_USER_PORT: &str = "no-user-port";
 const BLUETOOTH: &str = "bluetooth";
 const DAEMON: &str = "daemon";
 const DAEMON_PORT: &str = "daemon-port";
 const MAC_ADDRESS: &str = "mac-address";
 const PIN: &str = "pin";
 const VERBOSE: &str = "verbose";
+const TIMEOUT: &str = "timeout";
+const TIMEOUT_UPLOAD: &str = "timeout-upload";
+const CONNECT_TIMEOUT: &str = "connect-timeout";
+const MTU: &str = "mtu";
+pub(crate) const QUIET: &str = "quiet";
 
 fn main() {
-    env_logger::init();
-
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
@@ -38,6 +57,45 @@ async fn run() {
                 .conflicts_with(BLUETOOTH)
                 .conflicts_with(DAEMON),
         )
+        .arg(
+            Arg::new(BAUD)
+                .help("Baud rate of the serial connection")
+                .long(BAUD)
+                .default_value("115200")
+                .value_parser(
+                    PossibleValuesParser::new([
+                        "9600", "19200", "38400", "57600", "115200", "230400", "460800", "921600",
+                    ])
+                    .map(|rate| rate.parse::<u32>().expect("baud rate")),
+                )
+                .action(ArgAction::Set)
+                .conflicts_with(BLUETOOTH)
+                .conflicts_with(DAEMON),
+        )
+        .arg(
+            Arg::new(AUTO_RECONNECT)
+                .help("Reconnect to the brain if the serial connection is briefly lost")
+                .long(AUTO_RECONNECT)
+                .action(ArgAction::SetTrue)
+                .conflicts_with(BLUETOOTH)
+                .conflicts_with(DAEMON),
+        )
+        .arg(
+            Arg::new(CONTROLLER)
+                .help("Connect through a controller tethered over USB instead of directly to the brain")
+                .long(CONTROLLER)
+                .action(ArgAction::SetTrue)
+                .conflicts_with(BLUETOOTH)
+                .conflicts_with(DAEMON),
+        )
+        .arg(
+            Arg::new(NO_USER_PORT)
+                .help("Only open the brain's system port, for setups without the user port (disables user serial)")
+                .long(NO_USER_PORT)
+                .action(ArgAction::SetTrue)
+                .conflicts_with(BLUETOOTH)
+                .conflicts_with(DAEMON),
+        )
         .arg(
             Arg::new(BLUETOOTH)
                 .help("Connect to brain via bluetooth instead of a serial port")
@@ -50,6 +108,7 @@ async fn run() {
             Arg::new(MAC_ADDRESS)
                 .help("The MAC address of the brain to be used with bluetooth")
                 .short('m')
+                .value_parser(parse_mac_address)
                 .action(ArgAction::Set)
                 .requires(BLUETOOTH),
         )
@@ -57,6 +116,26 @@ async fn run() {
             Arg::new(PIN)
                 .help("The PIN of the brain to be used with bluetooth")
                 .short('i')
+                .value_parser(parse_pin)
+                .action(ArgAction::Set)
+                .requires(BLUETOOTH),
+        )
+        .arg(
+            Arg::new(CONNECT_TIMEOUT)
+                .help("How long to keep trying to connect to the brain, by finding its serial ports or searching for it over bluetooth, in milliseconds [default: 10000]")
+                .long(CONNECT_TIMEOUT)
+                .alias("scan-timeout")
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set)
+                .conflicts_with(DAEMON),
+        )
+        .arg(
+            Arg::new(MTU)
+                .help("Overrides the bluetooth ATT MTU, which sets the size of each packet (23-517)")
+                .long(MTU)
+                .value_parser(value_parser!(u16).range(
+                    *ATT_MTU_RANGE.start() as i64..=*ATT_MTU_RANGE.end() as i64,
+                ))
                 .action(ArgAction::Set)
                 .requires(BLUETOOTH),
         )
@@ -77,6 +156,22 @@ async fn run() {
                 .action(ArgAction::Set)
                 .requires(DAEMON),
         )
+        .arg(
+            Arg::new(TIMEOUT)
+                .help("How long to wait for the brain to respond, in milliseconds")
+                .long(TIMEOUT)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set)
+                .conflicts_with(DAEMON),
+        )
+        .arg(
+            Arg::new(TIMEOUT_UPLOAD)
+                .help("How long to wait for the brain to respond during file transfers, in milliseconds")
+                .long(TIMEOUT_UPLOAD)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set)
+                .conflicts_with(DAEMON),
+        )
         .arg(
             Arg::new(VERBOSE)
                 .help("Enables extra debug logging")
@@ -84,36 +179,105 @@ async fn run() {
                 .global(false)
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new(QUIET)
+                .help("Suppresses informational output")
+                .short('q')
+                .long(QUIET)
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
         .subcommand(competition::command())
         .subcommand(manage::command())
         .subcommand(terminal::command())
         .subcommand(upload::command())
-        .subcommand(daemon::command());
+        .subcommand(daemon::command())
+        .subcommand(completions::command());
     command.build();
 
     let root = command.get_matches_mut();
+    let config = config::Config::load();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if root.get_flag(VERBOSE) || config.verbose {
+        logger.filter_level(LevelFilter::Debug);
+    }
+    logger.init();
+
     match root.subcommand() {
         None => {
             command.print_help().expect("failed to print help");
         }
+        Some((completions::COMMAND, matches)) => {
+            completions::completions(&mut command, matches);
+        }
         Some((name, matches)) => {
-            let options = if root.get_flag(BLUETOOTH) {
+            let from_cli = |id: &str| root.value_source(id) == Some(ValueSource::CommandLine);
+            let default_timeouts = Timeouts::default();
+            let timeouts = Timeouts {
+                query: root
+                    .get_one::<u64>(TIMEOUT)
+                    .copied()
+                    .or(config.timeout)
+                    .map_or(default_timeouts.query, Duration::from_millis),
+                transfer: root
+                    .get_one::<u64>(TIMEOUT_UPLOAD)
+                    .copied()
+                    .or(config.timeout_upload)
+                    .map_or(default_timeouts.transfer, Duration::from_millis),
+                connect: root
+                    .get_one::<u64>(CONNECT_TIMEOUT)
+                    .copied()
+                    .or(config.connect_timeout)
+                    .map_or(default_timeouts.connect, Duration::from_millis),
+            };
+            let mut scan_hint = false;
+            let options = if root.get_flag(BLUETOOTH)
+                || (config.bluetooth && !from_cli(PORT) && !root.get_flag(DAEMON))
+            {
                 let mac_address: Option<&String> = root.get_one(MAC_ADDRESS);
                 let pin: Option<&String> = root.get_one(PIN);
+                let mac_address = mac_address.cloned().or(config.mac_address);
+                scan_hint = mac_address.is_none();
+                let mtu = root.get_one::<u16>(MTU).copied();
+                if let Some(mtu) = mtu.filter(|mtu| *mtu > DEFAULT_ATT_MTU) {
+                    println!(
+                        "{} MTU {} is above the default of {}, writes will fail if the adapter did not negotiate it",
+                        color::yellow("Warning:"),
+                        mtu,
+                        DEFAULT_ATT_MTU
+                    );
+                }
 
                 RobotConnectionOptions::Bluetooth {
-                    mac_address: mac_address.cloned(),
-                    pin: pin.cloned(),
+                    mac_address,
+                    pin: pin.cloned().or(config.pin),
+                    mtu,
+                    timeouts,
                 }
-            } else if root.get_flag(DAEMON) {
+            } else if root.get_flag(DAEMON) || (config.daemon && !from_cli(PORT)) {
+                let port = *root.get_one(DAEMON_PORT).expect("missing daemon port");
+
                 RobotConnectionOptions::Daemon {
-                    port: *root.get_one(DAEMON_PORT).expect("missing daemon port"),
+                    port: match config.daemon_port {
+                        Some(config_port) if !from_cli(DAEMON_PORT) => config_port,
+                        _ => port,
+                    },
                 }
             } else {
                 let port: Option<&String> = root.get_one(PORT);
+                let baud_rate = *root.get_one(BAUD).expect("missing baud rate");
 
                 RobotConnectionOptions::Serial {
-                    port: port.cloned(),
+                    port: port.cloned().or(config.port),
+                    baud_rate: match config.baud_rate {
+                        Some(config_rate) if !from_cli(BAUD) => config_rate,
+                        _ => baud_rate,
+                    },
+                    controller: root.get_flag(CONTROLLER),
+                    user_port: !root.get_flag(NO_USER_PORT),
+                    auto_reconnect: root.get_flag(AUTO_RECONNECT),
+                    timeouts,
                 }
             };
 
@@ -164,8 +328,62 @@ async fn run() {
                 }
             } {
                 Ok(_) => {}
-                Err(err) => println!("{}", err),
+                Err(err) => {
+                    eprintln!("{}", color::red(&err));
+                    if scan_hint
+                        && matches!(
+                            err,
+                            CommandError::ConnectionError(ConnectionError::DeviceNotFound)
+                        )
+                    {
+                        eprintln!("Pass the brain's MAC address with -m to connect to it directly");
+                    }
+                }
             };
         }
     }
 }
+
+/// Value parser for names sent to the brain, which only supports ASCII.
+pub(crate) fn parse_name(name: &str) -> Result<String, String> {
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if !name.is_ascii() {
+        return Err(format!(
+            "`{}` contains non-ASCII characters, which the brain cannot display",
+            name
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Value parser for bluetooth PINs, so an invalid PIN is reported before searching for the brain.
+fn parse_pin(pin: &str) -> Result<String, String> {
+    v5_serial::connection::parse_pin(pin)
+        .map(|_| pin.to_string())
+        .map_err(|_| "the PIN must be the 4 digits shown on the brain".to_string())
+}
+
+/// Value parser for MAC addresses, which are accepted in any of the common formats.
+fn parse_mac_address(address: &str) -> Result<String, String> {
+    v5_serial::connection::parse_mac_address(address)
+        .map(|_| address.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// Tells the user whether they are connected directly to a brain or through a controller.
+pub(crate) async fn report_connection(brain: &mut Brain, quiet: bool) -> Result<(), CommandError> {
+    match brain.get_system_version().await?.product() {
+        Product::Controller { has_robot: false } => println!(
+            "{} connected to a controller that is not linked to a brain",
+            color::yellow("Warning:")
+        ),
+        _ if quiet => {}
+        Product::Controller { has_robot: true } => {
+            println!("{}", color::green("Connected to brain through controller"))
+        }
+        Product::Brain => println!("{}", color::green("Connected to brain")),
+    }
+    Ok(())
+}
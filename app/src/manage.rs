@@ -1,38 +1,103 @@
-use clap::{Arg, ArgMatches, Command, value_parser};
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser, ValueHint};
 use clap::builder::NonEmptyStringValueParser;
+use ini::Ini;
+use libdeflater::Decompressor;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
-use v5_serial::brain::filesystem::{DeleteFlags, FileFlags, Vid};
-use v5_serial::brain::system::{ExecutionFlags, KernelVariable};
-use v5_serial::connection::RobotConnectionOptions;
-use v5_serial::error::CommandError;
+use v5_serial::brain::{Brain, NAME_LEN};
+use v5_serial::brain::filesystem::{
+    default_load_address, DeleteFlags, FileFlags, FileMetadata, FileType, TransferTarget,
+    UploadAction, VerifiedDownload, Vid, CRC32,
+};
+use v5_serial::brain::system::{ExecutionFlags, KernelVariable, SCREEN_HEIGHT, SCREEN_WIDTH};
+use v5_serial::connection::{Nack, RobotConnectionOptions};
+use v5_serial::error::{CommandError, CommunicationError, ParseError};
+
+use crate::color::{green, yellow};
+use crate::table::Table;
+use crate::upload::{slot_file_name, upload_file, FILE_VERSION};
 
 pub(crate) const COMMAND: &str = "manage";
 
 const STATUS: &str = "status";
 const METADATA: &str = "metadata";
 const LIST_FILES: &str = "ls_files";
+const LIST_VIDS: &str = "list-vids";
 const FILE_NAME: &str = "file_name";
 const VID: &str = "vid";
 const OPTION: &str = "option";
+const TREE: &str = "tree";
+const FILE_TYPE: &str = "type";
+const SORT: &str = "sort";
 const STOP: &str = "stop";
 const RUN: &str = "run";
 const SLOT: &str = "slot";
 const REMOVE_ALL_PROGRAMS: &str = "rm_all";
 const REMOVE_FILE: &str = "rm_file";
 const REMOVE_PROGRAM: &str = "rm_program";
+const OLDER_THAN: &str = "older-than";
 const KERNEL_VARIABLE: &str = "variable";
 const SET: &str = "set";
 const GET: &str = "get";
+const LIST: &str = "list";
 const VARIABLE: &str = "variable";
 const VALUE: &str = "value";
 const CAPTURE: &str = "capture";
+const FIND_SLOT: &str = "find-slot";
+const OUTPUT: &str = "output";
+const REGION: &str = "region";
+const WATCH: &str = "watch";
+const STREAM: &str = "stream";
+const ALL: &str = "all";
+const DUMP_FLASH: &str = "dump-flash";
+const DIRECTORY: &str = "directory";
+const RESTORE: &str = "restore";
+const DOWNLOAD: &str = "download";
+const DECOMPRESS: &str = "decompress";
+const TO_STDOUT: &str = "to-stdout";
+const CAT: &str = "cat";
+const HEX: &str = "hex";
+const PING: &str = "ping";
+const BOOTLOADER_VERSION: &str = "bootloader-version";
+const VERSION: &str = "version";
+const EXPORT_INI: &str = "export-ini";
+const IMPORT_INI: &str = "import-ini";
+const UPLOAD_FILE: &str = "upload-file";
+const NUKE: &str = "nuke";
+const YES: &str = "yes";
+const NAME: &str = "name";
+const PATH: &str = "path";
+const COUNT: &str = "count";
+
+/// Files larger than this are not printed by `cat`, as they are almost certainly programs.
+const MAX_CAT_SIZE: u32 = 64 * 1024;
 
 pub(crate) fn command() -> Command {
     Command::new(COMMAND)
         .about("Manage the robot brain")
-        .subcommand(Command::new(STATUS).about("Get the status of the robot brain"))
+        .subcommand(
+            Command::new(STATUS)
+                .about("Get the status of the robot brain")
+                .arg(
+                    Arg::new(WATCH)
+                        .long(WATCH)
+                        .help("Refresh the status every given number of seconds until interrupted")
+                        .value_name("interval")
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new(ALL)
+                        .long(ALL)
+                        .help("Also print the product, team number, robot name and storage used")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand(
             Command::new(METADATA)
                 .about("Reads file metadata")
@@ -40,7 +105,7 @@ pub(crate) fn command() -> Command {
                     Arg::new(FILE_NAME)
                         .index(1)
                         .required(true)
-                        .value_parser(NonEmptyStringValueParser::new()),
+                        .value_parser(crate::parse_name),
                 )
                 .arg(
                     Arg::new(VID)
@@ -55,26 +120,68 @@ pub(crate) fn command() -> Command {
                         .value_parser(value_parser!(u8)),
                 ),
         )
+        .subcommand(
+            Command::new(LIST_VIDS)
+                .about("Shows how many files are stored under each VID")
+                .arg(
+                    Arg::new(ALL)
+                        .long(ALL)
+                        .help("Also check every other VID (0-255) and show those with files")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand(
             Command::new(LIST_FILES)
                 .about("Lists all files on the brain")
                 .arg(
                     Arg::new(VID)
                         .short('v')
+                        .long(VID)
+                        .help("Only list files with this VID")
                         .default_value("1")
                         .value_parser(value_parser!(u8)),
                 )
+                .arg(
+                    Arg::new(FILE_TYPE)
+                        .long(FILE_TYPE)
+                        .help("Only list files of this type")
+                        .value_parser(["bin", "ini"]),
+                )
+                .arg(
+                    Arg::new(SORT)
+                        .long(SORT)
+                        .help("Sort the listed files (defaults to brain order)")
+                        .value_parser(["name", "size", "date"]),
+                )
                 .arg(
                     Arg::new(OPTION)
                         .short('o')
                         .default_value("0")
                         .value_parser(value_parser!(u8)),
+                )
+                .arg(
+                    Arg::new(TREE)
+                        .long(TREE)
+                        .help("Groups files by the '/' in their names and prints them as a tree")
+                        .conflicts_with(OUTPUT)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new(OUTPUT)
+                        .long(OUTPUT)
+                        .help("How to print the listed files")
+                        .value_parser(["table", "csv"]),
                 ),
         )
         .subcommand(Command::new(STOP).about("Terminates a running program"))
         .subcommand(
             Command::new(RUN)
                 .about("Starts a program on the robot")
+                .after_help(
+                    "The brain has no default or startup program: it always boots to its home \
+                     screen, and the protocol has no command to change that. Start the program \
+                     with this command (or `upload --after-upload run`) after each power cycle.",
+                )
                 .arg(
                     Arg::new(SLOT)
                         .index(1)
@@ -88,6 +195,22 @@ pub(crate) fn command() -> Command {
                         .value_parser(value_parser!(u8)),
                 ),
         )
+        .subcommand(
+            Command::new(FIND_SLOT)
+                .about("Finds which slot a program file is installed in")
+                .arg(
+                    Arg::new(FILE_NAME)
+                        .index(1)
+                        .required(true)
+                        .value_parser(crate::parse_name),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .default_value("1")
+                        .value_parser(value_parser!(u8)),
+                ),
+        )
         .subcommand(
             Command::new(REMOVE_ALL_PROGRAMS)
                 .about("Deletes all programs from the robot")
@@ -98,14 +221,39 @@ pub(crate) fn command() -> Command {
                         .value_parser(value_parser!(u8)),
                 ),
         )
+        .subcommand(
+            Command::new(NUKE)
+                .about("Deletes every file in the user, PROS, RMS and MW VIDs, including cold packages")
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .help("Additional VID to wipe (may be repeated)")
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(u8)),
+                )
+                .arg(
+                    Arg::new(YES)
+                        .short('y')
+                        .long(YES)
+                        .help("Deletes the files without asking for confirmation")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand(
             Command::new(REMOVE_FILE)
                 .about("Removes a file from the robot (by name)")
                 .arg(
                     Arg::new(FILE_NAME)
                         .index(1)
-                        .required(true)
-                        .value_parser(NonEmptyStringValueParser::new()),
+                        .required_unless_present(OLDER_THAN)
+                        .value_parser(crate::parse_name),
+                )
+                .arg(
+                    Arg::new(OLDER_THAN)
+                        .long(OLDER_THAN)
+                        .help("Removes all files older than the given age (e.g. 30m, 12h, 7d)")
+                        .conflicts_with(FILE_NAME)
+                        .value_parser(parse_age),
                 )
                 .arg(
                     Arg::new(VID)
@@ -130,10 +278,222 @@ pub(crate) fn command() -> Command {
                         .value_parser(value_parser!(u8)),
                 ),
         )
-        .subcommand(Command::new(CAPTURE).about("Captures a screenshot of the V5 brain's screen"))
+        .subcommand(
+            Command::new(CAPTURE)
+                .about("Captures a screenshot of the V5 brain's screen")
+                .arg(
+                    Arg::new(OUTPUT)
+                        .short('o')
+                        .help("Where to save the screenshot (PPM image)")
+                        .default_value("screenshot.ppm")
+                        .value_hint(ValueHint::FilePath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new(REGION)
+                        .long(REGION)
+                        .help("Only capture part of the screen, given as x,y,width,height")
+                        .value_parser(parse_region),
+                )
+                .arg(
+                    Arg::new(STREAM)
+                        .long(STREAM)
+                        .help("Capture every given number of milliseconds until interrupted, numbering each frame (e.g. screenshot_0000.ppm)")
+                        .value_name("interval")
+                        .value_parser(value_parser!(u64).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new(DOWNLOAD)
+                .about("Downloads a file from the brain")
+                .arg(
+                    Arg::new(FILE_NAME)
+                        .index(1)
+                        .required(true)
+                        .value_parser(crate::parse_name),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .default_value("1")
+                        .value_parser(value_parser!(u8)),
+                )
+                .arg(
+                    Arg::new(OUTPUT)
+                        .short('o')
+                        .help(
+                            "Where to save the file (defaults to its name on the brain, \
+                             `-` for stdout)",
+                        )
+                        .value_hint(ValueHint::FilePath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new(TO_STDOUT)
+                        .long(TO_STDOUT)
+                        .help("Writes the file to stdout so it can be piped, same as `-o -`")
+                        .conflicts_with(OUTPUT)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new(DECOMPRESS)
+                        .long(DECOMPRESS)
+                        .help("Decompresses gzipped programs back into the original binary")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new(CAT)
+                .about("Prints the contents of a small file, such as a program's ini")
+                .arg(
+                    Arg::new(FILE_NAME)
+                        .index(1)
+                        .required(true)
+                        .value_parser(crate::parse_name),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .default_value("1")
+                        .value_parser(value_parser!(u8)),
+                )
+                .arg(
+                    Arg::new(HEX)
+                        .long(HEX)
+                        .help("Prints the contents as hex instead of text")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new(UPLOAD_FILE)
+                .about("Uploads a file, keeping any directories in its path as part of its name")
+                .arg(
+                    Arg::new(PATH)
+                        .index(1)
+                        .required(true)
+                        .value_hint(ValueHint::FilePath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new(NAME)
+                        .short('n')
+                        .long(NAME)
+                        .help("Name of the file on the brain (defaults to the path)")
+                        .value_parser(parse_remote_name),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .default_value("1")
+                        .value_parser(value_parser!(u8)),
+                ),
+        )
+        .subcommand(
+            Command::new(EXPORT_INI)
+                .about("Saves the ini describing the program in a slot, so it can be edited")
+                .arg(
+                    Arg::new(SLOT)
+                        .index(1)
+                        .required(true)
+                        .value_parser(value_parser!(u8).range(1..=8)),
+                )
+                .arg(
+                    Arg::new(PATH)
+                        .index(2)
+                        .required(true)
+                        .value_hint(ValueHint::FilePath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .default_value("1")
+                        .value_parser(value_parser!(u8)),
+                ),
+        )
+        .subcommand(
+            Command::new(IMPORT_INI)
+                .about("Replaces the ini describing the program in a slot, keeping the program itself")
+                .arg(
+                    Arg::new(SLOT)
+                        .index(1)
+                        .required(true)
+                        .value_parser(value_parser!(u8).range(1..=8)),
+                )
+                .arg(
+                    Arg::new(PATH)
+                        .index(2)
+                        .required(true)
+                        .value_hint(ValueHint::FilePath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .default_value("1")
+                        .value_parser(value_parser!(u8)),
+                ),
+        )
+        .subcommand(
+            Command::new(DUMP_FLASH)
+                .about("Downloads every file in a VID to a directory, along with a manifest of their metadata")
+                .arg(
+                    Arg::new(DIRECTORY)
+                        .index(1)
+                        .required(true)
+                        .value_hint(ValueHint::DirPath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                )
+                .arg(
+                    Arg::new(VID)
+                        .short('v')
+                        .help("VID to download (may be repeated)")
+                        .default_value("1")
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(u8)),
+                ),
+        )
+        .subcommand(
+            Command::new(RESTORE)
+                .about("Re-uploads the files in a directory created by dump-flash")
+                .arg(
+                    Arg::new(DIRECTORY)
+                        .index(1)
+                        .required(true)
+                        .value_hint(ValueHint::DirPath)
+                        .value_parser(NonEmptyStringValueParser::new()),
+                ),
+        )
+        .subcommand(
+            Command::new(PING)
+                .about("Measures the round-trip latency of the connection")
+                .arg(
+                    Arg::new(COUNT)
+                        .short('n')
+                        .help("Number of packets to send")
+                        .default_value("10")
+                        .value_parser(value_parser!(u32).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new(VERSION)
+                .about("Prints the product and the versions of its firmware")
+                .arg(
+                    Arg::new(OUTPUT)
+                        .long(OUTPUT)
+                        .help("How to print the versions")
+                        .default_value("text")
+                        .value_parser(["text", "json"]),
+                ),
+        )
+        .subcommand(
+            Command::new(BOOTLOADER_VERSION)
+                .about("Prints the version of the brain's golden (boot) firmware image"),
+        )
         .subcommand(
             Command::new(KERNEL_VARIABLE)
                 .about("Management of kernel variables")
+                .subcommand(Command::new(LIST).about("Prints the value of every kernel variable"))
                 .subcommand(
                     Command::new(GET)
                         .about("Gets the value of a kernel variable")
@@ -153,7 +513,12 @@ pub(crate) fn command() -> Command {
                                 .required(true)
                                 .value_parser(["team_number", "robot_name"]),
                         )
-                        .arg(Arg::new(VALUE).index(2).required(true)),
+                        .arg(
+                            Arg::new(VALUE)
+                                .index(2)
+                                .required(true)
+                                .value_parser(crate::parse_name),
+                        ),
                 ),
         )
 }
@@ -164,25 +529,39 @@ pub(crate) async fn manage(
     options: RobotConnectionOptions,
 ) -> Result<(), CommandError> {
     if let Some((command, args)) = args.subcommand() {
+        let mut brain = v5_serial::connection::connect_to_brain(options).await?;
         match command {
-            STATUS => get_status(options).await,
-            METADATA => get_metadata(options, args).await,
-            LIST_FILES => list_files(options, args).await,
-            STOP => stop_execution(options).await,
-            RUN => execute_program(options, args).await,
-            REMOVE_ALL_PROGRAMS => remove_all_programs(options, args).await,
-            REMOVE_FILE => remove_file(options, args).await,
-            REMOVE_PROGRAM => remove_program(options, args).await,
+            STATUS => get_status(&mut brain, args).await,
+            METADATA => get_metadata(&mut brain, args).await,
+            LIST_FILES => list_files(&mut brain, args).await,
+            LIST_VIDS => list_vids(&mut brain, args).await,
+            STOP => stop_execution(&mut brain).await,
+            RUN => execute_program(&mut brain, args).await,
+            FIND_SLOT => find_slot(&mut brain, args).await,
+            REMOVE_ALL_PROGRAMS => remove_all_programs(&mut brain, args).await,
+            REMOVE_FILE => remove_file(&mut brain, args).await,
+            NUKE => nuke(&mut brain, args).await,
+            REMOVE_PROGRAM => remove_program(&mut brain, args).await,
             KERNEL_VARIABLE => {
                 kernel_variable(
                     cmd.find_subcommand_mut(KERNEL_VARIABLE)
                         .expect("get subcommand"),
-                    options,
+                    &mut brain,
                     args,
                 )
                 .await
             }
-            CAPTURE => capture_screen(options, args).await,
+            CAPTURE => capture_screen(&mut brain, args).await,
+            DOWNLOAD => download(&mut brain, args).await,
+            CAT => cat(&mut brain, args).await,
+            UPLOAD_FILE => upload_single_file(&mut brain, args).await,
+            EXPORT_INI => export_ini(&mut brain, args).await,
+            IMPORT_INI => import_ini(&mut brain, args).await,
+            DUMP_FLASH => dump_flash(&mut brain, args).await,
+            RESTORE => restore(&mut brain, args).await,
+            PING => ping(&mut brain, args).await,
+            BOOTLOADER_VERSION => bootloader_version(&mut brain).await,
+            VERSION => version(&mut brain, args).await,
             _ => {
                 cmd.print_long_help().expect("print help");
                 Err(CommandError::InvalidSubcommand)
@@ -194,22 +573,120 @@ pub(crate) async fn manage(
     }
 }
 
-async fn get_status(options: RobotConnectionOptions) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn get_status(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let Some(interval) = args.get_one::<u64>(WATCH) else {
+        return print_status(brain, args.get_flag(ALL)).await;
+    };
 
+    let interval = Duration::from_secs(*interval);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        tokio::select! {
+            _ = &mut ctrl_c => return Ok(()),
+            result = async {
+                // clear the screen and move the cursor to the top left, like watch(1)
+                print!("\x1b[2J\x1b[H");
+                print_status(brain, args.get_flag(ALL)).await?;
+                tokio::time::sleep(interval).await;
+                Ok::<(), CommandError>(())
+            } => result?,
+        }
+    }
+}
+
+async fn print_status(brain: &mut Brain, all: bool) -> Result<(), CommandError> {
     let status = brain.get_system_status().await?;
     println!(
         "System Version: {}\nCPU 0: {}\nCPU 1: {}\nTouch: {}\nSystem ID: {}",
         status.system, status.cpu0, status.cpu1, status.touch, status.system_id
     );
+    if let Some(details) = status.details {
+        println!(
+            "System Flags: {:#06x}\nGolden Version: {}\nROM Bootloader: {}\nRAM Bootloader: {}",
+            details.flags,
+            details.golden_version,
+            details.rom_bootloader_active,
+            details.ram_bootloader_active
+        );
+    }
+    if all {
+        let version = brain.get_system_version().await?;
+        let team_number = brain
+            .get_kernel_variable(KernelVariable::TeamNumber)
+            .await?;
+        let robot_name = brain.get_kernel_variable(KernelVariable::RobotName).await?;
+        println!(
+            "Product: {}\nTeam Number: {}\nRobot Name: {}",
+            version, team_number, robot_name
+        );
+        // the protocol has no query for free space, so only the space used is shown
+        for vid in [Vid::User, Vid::Pros] {
+            let files = brain.list_files(vid, FileFlags::empty()).await?;
+            println!(
+                "Storage ({}): {} file(s), {} bytes",
+                vid,
+                files.len(),
+                files.iter().map(|meta| meta.size as u64).sum::<u64>()
+            );
+        }
+    }
     Ok(())
 }
 
-async fn get_metadata(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn ping(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let count = *args.get_one::<u32>(COUNT).expect("missing count");
+    let latency = brain
+        .ping(NonZeroU32::new(count).expect("count is at least 1"))
+        .await?;
+    println!(
+        "{} packets: min {:.1?}, avg {:.1?}, max {:.1?}",
+        count, latency.min, latency.avg, latency.max
+    );
+    Ok(())
+}
+
+async fn version(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let product = brain.get_system_version().await?.product();
+    let status = brain.get_system_status().await?;
+    let golden = status.details.map(|details| details.golden_version);
+    if args.get_one::<String>(OUTPUT).map(String::as_str) == Some("json") {
+        // one object per line, so output from many brains can be collected by a script
+        let version = serde_json::json!({
+            "product": product.to_string(),
+            "system": status.system.to_string(),
+            "cpu0": status.cpu0.to_string(),
+            "cpu1": status.cpu1.to_string(),
+            "touch": status.touch.to_string(),
+            "golden": golden.map(|golden| golden.to_string()),
+            "system_id": status.system_id,
+        });
+        println!("{}", version);
+    } else {
+        println!(
+            "Product: {}\nSystem Version: {}\nCPU 0: {}\nCPU 1: {}\nTouch: {}",
+            product, status.system, status.cpu0, status.cpu1, status.touch
+        );
+        if let Some(golden) = golden {
+            println!("Golden Version: {}", golden);
+        }
+        println!("System ID: {}", status.system_id);
+    }
+    Ok(())
+}
+
+async fn bootloader_version(brain: &mut Brain) -> Result<(), CommandError> {
+    match brain.get_bootloader_version().await? {
+        Some(version) => println!("{}", version),
+        None => println!(
+            "{}",
+            crate::color::yellow("This brain's firmware does not report its bootloader version")
+        ),
+    }
+    Ok(())
+}
+
+async fn get_metadata(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let metadata = brain
         .get_file_metadata_by_name(
             Vid::from(*args.get_one::<u8>(VID).expect("missing VID")),
@@ -235,54 +712,125 @@ async fn get_metadata(
     Ok(())
 }
 
-async fn list_files(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
-    let amount = brain
-        .get_directory_count(
-            Vid::from(*args.get_one::<u8>(VID).expect("missing VID")),
+async fn list_vids(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let mut table = Table::new(vec!["Vid", "Files"]);
+    for vid in Vid::KNOWN {
+        let count = match brain.get_directory_count(vid, FileFlags::empty()).await {
+            Ok(count) => count.to_string(),
+            Err(CommunicationError::NegativeAcknowledgement(_)) => "unavailable".to_string(),
+            Err(err) => return Err(err.into()),
+        };
+        table.add_row(vec![vid.to_string(), count]);
+    }
+    if args.get_flag(ALL) {
+        for id in 0..=u8::MAX {
+            let vid = Vid::from(id);
+            if !matches!(vid, Vid::Custom(_)) {
+                continue;
+            }
+            // unused VIDs are either empty or rejected, neither of which is worth listing
+            match brain.get_directory_count(vid, FileFlags::empty()).await {
+                Ok(0) | Err(CommunicationError::NegativeAcknowledgement(_)) => {}
+                Ok(count) => table.add_row(vec![vid.to_string(), count.to_string()]),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    table.print();
+    Ok(())
+}
+
+async fn list_files(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = *args.get_one::<u8>(VID).expect("missing VID");
+    let file_type = args.get_one::<String>(FILE_TYPE);
+    let mut files = brain
+        .list_files(
+            Vid::from(vid),
             args.get_one::<u8>(OPTION)
                 .map(|b| FileFlags::from_bits_retain(*b))
                 .unwrap_or(FileFlags::empty()),
         )
         .await?;
+    if let Some(file_type) = file_type {
+        files.retain(|meta| meta.file_type.eq_ignore_ascii_case(file_type));
+    }
 
-    for i in 0_u8..amount as u8 {
-        let meta = brain
-            .get_file_metadata_by_index(i, FileFlags::empty())
-            .await?;
-        println!(
-            "Name: {}\nVid: {}\nVersion: {}\nSize: {}\nAddress: {}\nCRC: {}\nFile Type: {}\nTimestamp: {}\n",
+    match args.get_one::<String>(SORT).map(String::as_str) {
+        Some("name") => files.sort_by(|a, b| a.name.cmp(&b.name)),
+        Some("size") => files.sort_by_key(|meta| meta.size),
+        Some("date") => files.sort_by_key(|meta| meta.timestamp),
+        _ => {}
+    }
+
+    if args.get_flag(TREE) && files.iter().any(|meta| meta.name.contains('/')) {
+        print_file_tree(&files);
+        return Ok(());
+    }
+
+    let mut table = Table::new(vec![
+        "Name",
+        "Vid",
+        "Version",
+        "Size",
+        "Address",
+        "CRC",
+        "File Type",
+        "Timestamp",
+    ]);
+    for meta in files {
+        let version = meta.file_version();
+        table.add_row(vec![
             meta.name,
-            meta.vid,
-            meta.version,
-            meta.size,
-            meta.addr,
-            meta.crc,
+            meta.vid.to_string(),
+            version.to_string(),
+            meta.size.to_string(),
+            format!("{:#010x}", meta.addr),
+            format!("{:#010x}", meta.crc),
             meta.file_type,
             OffsetDateTime::from(meta.timestamp)
                 .format(&Rfc3339)
-                .expect("parse timestamp")
-        );
+                .expect("parse timestamp"),
+        ]);
+    }
+    match args.get_one::<String>(OUTPUT).map(String::as_str) {
+        Some("csv") => table.print_csv(),
+        _ => table.print(),
     }
     Ok(())
 }
 
-async fn stop_execution(options: RobotConnectionOptions) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
-    brain
-        .execute_program(Vid::User, ExecutionFlags::STOP, "")
-        .await?;
+fn print_file_tree(files: &[FileMetadata]) {
+    let mut files: Vec<&FileMetadata> = files.iter().collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut current: Vec<&str> = Vec::new();
+    for meta in files {
+        let mut components: Vec<&str> = meta.name.split('/').collect();
+        let file_name = components.pop().expect("file name");
+        let shared = current
+            .iter()
+            .zip(&components)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, directory) in components.iter().enumerate().skip(shared) {
+            println!("{}{}/", "  ".repeat(depth), directory);
+        }
+        println!(
+            "{}{} ({} bytes)",
+            "  ".repeat(components.len()),
+            file_name,
+            meta.size
+        );
+        current = components;
+    }
+}
+
+async fn stop_execution(brain: &mut Brain) -> Result<(), CommandError> {
+    brain.stop_program().await?;
     Ok(())
 }
 
-async fn execute_program(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn execute_program(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
     let slot = *args.get_one::<u8>(SLOT).expect("no slot provided");
     brain
@@ -291,24 +839,25 @@ async fn execute_program(
     Ok(())
 }
 
-async fn remove_all_programs(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn find_slot(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
-    let c = brain.get_directory_count(vid, FileFlags::empty()).await?;
-    let mut vec = Vec::new();
-    vec.reserve(c as usize);
-    for i in 0_u8..c as u8 {
-        vec.push(
-            brain
-                .get_file_metadata_by_index(i, FileFlags::empty())
-                .await?,
-        );
+    let name = args.get_one::<String>(FILE_NAME).expect("missing name");
+    match brain
+        .get_program_file_slot(vid, FileFlags::empty(), name)
+        .await
+    {
+        Ok(slot) => println!("{}", slot + 1),
+        Err(CommunicationError::NegativeAcknowledgement(Nack::ProgramFileError)) => {
+            println!("{} is not installed", name)
+        }
+        Err(err) => return Err(err.into()),
     }
+    Ok(())
+}
 
-    for meta in vec {
+async fn remove_all_programs(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    for meta in brain.list_files(vid, FileFlags::empty()).await? {
         brain
             .delete_file(vid, DeleteFlags::ERASE_ALL, &meta.name)
             .await?;
@@ -316,12 +865,90 @@ async fn remove_all_programs(
     Ok(())
 }
 
-async fn remove_file(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn nuke(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    // the system VID is left alone, as it holds files the firmware needs
+    let mut vids = vec![Vid::User, Vid::Pros, Vid::Rms, Vid::Mw];
+    for vid in args.get_many::<u8>(VID).into_iter().flatten() {
+        let vid = Vid::from(*vid);
+        if !vids.iter().any(|known| u8::from(*known) == u8::from(vid)) {
+            vids.push(vid);
+        }
+    }
+
+    let mut files = Vec::new();
+    for vid in vids {
+        for meta in brain.list_files(vid, FileFlags::empty()).await? {
+            println!("{}\t{}", meta.vid, meta.name);
+            files.push((vid, meta.name));
+        }
+    }
+    if files.is_empty() {
+        if !args.get_flag(crate::QUIET) {
+            println!("There are no files to delete");
+        }
+        return Ok(());
+    }
+
+    if !args.get_flag(YES) {
+        print!(
+            "{} this permanently deletes {} file(s). Type `{}` to continue: ",
+            yellow("Warning:"),
+            files.len(),
+            NUKE
+        );
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if answer.trim() != NUKE {
+            return Ok(());
+        }
+    }
+
+    for (vid, name) in files {
+        brain
+            .delete_file(vid, DeleteFlags::ERASE_ALL, &name)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn remove_file(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    if let Some(age) = args.get_one::<Duration>(OLDER_THAN) {
+        let cutoff = SystemTime::now()
+            .checked_sub(*age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let stale: Vec<String> = brain
+            .list_files(vid, FileFlags::empty())
+            .await?
+            .into_iter()
+            .filter(|meta| meta.timestamp < cutoff)
+            .map(|meta| meta.name)
+            .collect();
+
+        if stale.is_empty() {
+            if !args.get_flag(crate::QUIET) {
+                println!("No files are older than the given age");
+            }
+            return Ok(());
+        }
+        for name in &stale {
+            println!("{}", name);
+        }
+        print!("Delete {} file(s)? [y/N] ", stale.len());
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+
+        for name in stale {
+            brain.delete_file(vid, DeleteFlags::empty(), &name).await?;
+        }
+        return Ok(());
+    }
+
     let name = args
         .get_one::<String>(FILE_NAME)
         .expect("missing name")
@@ -330,11 +957,29 @@ async fn remove_file(
     Ok(())
 }
 
-async fn remove_program(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+fn parse_age(age: &str) -> Result<Duration, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (amount, unit) = age.split_at(split);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| format!("invalid age `{}`", age))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => {
+            return Err(format!(
+                "unknown unit in `{}` (expected s, m, h, d or w)",
+                age
+            ));
+        }
+    };
+    Ok(Duration::from_secs(amount * seconds))
+}
+
+async fn remove_program(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
     let slot = *args.get_one::<u8>(SLOT).expect("missing slot");
     brain
@@ -348,13 +993,14 @@ async fn remove_program(
 
 async fn kernel_variable(
     cmd: &mut Command,
-    options: RobotConnectionOptions,
+    brain: &mut Brain,
     args: &ArgMatches,
 ) -> Result<(), CommandError> {
     if let Some((command, args)) = args.subcommand() {
         match command {
-            GET => get_kernel_variable(options, args).await,
-            SET => set_kernel_variable(options, args).await,
+            LIST => list_kernel_variables(brain).await,
+            GET => get_kernel_variable(brain, args).await,
+            SET => set_kernel_variable(brain, args).await,
             _ => {
                 cmd.print_long_help().expect("print help");
                 Err(CommandError::InvalidSubcommand)
@@ -366,11 +1012,21 @@ async fn kernel_variable(
     }
 }
 
-async fn get_kernel_variable(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn list_kernel_variables(brain: &mut Brain) -> Result<(), CommandError> {
+    for variable in KernelVariable::ALL {
+        match brain.get_kernel_variable(variable).await {
+            Ok(value) => println!("{}={}", variable.get_name(), value),
+            // older firmware does not support every variable
+            Err(CommunicationError::NegativeAcknowledgement(_)) => {
+                println!("{}=<unavailable>", variable.get_name())
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+async fn get_kernel_variable(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let variable = KernelVariable::try_from(
         &*args
             .get_one::<String>(VARIABLE)
@@ -382,11 +1038,7 @@ async fn get_kernel_variable(
     Ok(())
 }
 
-async fn set_kernel_variable(
-    options: RobotConnectionOptions,
-    args: &ArgMatches,
-) -> Result<(), CommandError> {
-    let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+async fn set_kernel_variable(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
     let variable = KernelVariable::try_from(
         &*args
             .get_one::<String>(VARIABLE)
@@ -398,10 +1050,471 @@ async fn set_kernel_variable(
     Ok(())
 }
 
-async fn capture_screen(
-    options: RobotConnectionOptions,
-    _args: &ArgMatches,
+async fn capture_screen(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let output = args.get_one::<String>(OUTPUT).expect("output path");
+    let region = args
+        .get_one::<(u16, u16, u16, u16)>(REGION)
+        .copied()
+        .unwrap_or((0, 0, SCREEN_WIDTH, SCREEN_HEIGHT));
+
+    let Some(interval) = args.get_one::<u64>(STREAM) else {
+        return save_screen(brain, region, Path::new(output)).await;
+    };
+
+    // reading the whole screen takes a while, so this is only good for a few frames per second
+    let interval = Duration::from_millis(*interval);
+    let output = Path::new(output);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut frame = 0_u32;
+    loop {
+        tokio::select! {
+            _ = &mut ctrl_c => {
+                println!("Captured {} frame(s)", frame);
+                return Ok(());
+            }
+            result = async {
+                let start = Instant::now();
+                save_screen(brain, region, &frame_path(output, frame)).await?;
+                tokio::time::sleep(interval.saturating_sub(start.elapsed())).await;
+                Ok::<(), CommandError>(())
+            } => result?,
+        }
+        frame += 1;
+    }
+}
+
+/// Saves part of the screen as a PPM image.
+async fn save_screen(
+    brain: &mut Brain,
+    (x, y, width, height): (u16, u16, u16, u16),
+    output: &Path,
 ) -> Result<(), CommandError> {
-    let _brain = v5_serial::connection::connect_to_brain(options).await?;
+    let pixels = brain.read_screen(x, y, width, height).await?;
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    image.extend_from_slice(&pixels);
+    std::fs::write(output, image)?;
+    Ok(())
+}
+
+/// Numbers a frame of a stream, e.g. `screenshot.ppm` becomes `screenshot_0001.ppm`.
+fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map_or_else(|| "screenshot".into(), |stem| stem.to_string_lossy());
+    let name = match output.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    output.with_file_name(name)
+}
+
+async fn download(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    let name = args.get_one::<String>(FILE_NAME).expect("missing name");
+    let output = args.get_one::<String>(OUTPUT).unwrap_or(name);
+    let to_stdout = args.get_flag(TO_STDOUT) || output == "-";
+    // when piping, stdout only gets the file's contents
+    let report = |message: String| {
+        if to_stdout {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    };
+
+    let download = brain.read_file(vid, name).await?;
+    if download.crc_matches && !args.get_flag(crate::QUIET) {
+        report(format!("{} {}", green("CRC verified:"), name));
+    }
+    warn_crc_mismatch(name, &download);
+    let mut data = download.data;
+    if args.get_flag(DECOMPRESS) {
+        match gunzip(&data) {
+            Ok(decompressed) => data = decompressed,
+            Err(GunzipError::NotGzip) => report(format!(
+                "{} {} is not gzip compressed, saving it as is",
+                yellow("Warning:"),
+                name
+            )),
+            Err(GunzipError::Corrupt) => report(format!(
+                "{} {} has corrupt gzip data, saving it as is",
+                yellow("Warning:"),
+                name
+            )),
+        }
+    }
+    if to_stdout {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&data)?;
+        stdout.flush()?;
+    } else {
+        std::fs::write(output, data)?;
+    }
+    Ok(())
+}
+
+/// Warns when a file still didn't match its CRC after [`Brain::download_file_verified`] gave up.
+/// This goes to stderr, as the file itself may be going to stdout (`cat`, `download -o -`).
+fn warn_crc_mismatch(name: &str, download: &VerifiedDownload) {
+    if !download.crc_matches {
+        eprintln!(
+            "{} {} does not match its recorded CRC after {} attempts",
+            yellow("Warning:"),
+            name,
+            download.attempts
+        );
+    }
+}
+
+async fn cat(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    let name = args.get_one::<String>(FILE_NAME).expect("missing name");
+
+    let metadata = brain
+        .get_file_metadata_by_name(vid, FileFlags::empty(), name)
+        .await?;
+    if metadata.size > MAX_CAT_SIZE {
+        println!(
+            "{} {} is {} bytes, use download to save it instead",
+            yellow("Warning:"),
+            name,
+            metadata.size
+        );
+        return Ok(());
+    }
+    let download = brain.download_file_verified(&metadata).await?;
+    warn_crc_mismatch(name, &download);
+    let data = download.data;
+
+    match std::str::from_utf8(&data) {
+        Ok(text) if !args.get_flag(HEX) => print!("{}", text),
+        _ => {
+            for (i, line) in data.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+                println!("{:08x}  {}", i * 16, hex.join(" "));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn upload_single_file(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    let path = args.get_one::<String>(PATH).expect("missing path");
+    let name = match args.get_one::<String>(NAME) {
+        Some(name) => name.clone(),
+        None => parse_remote_name(path.trim_start_matches("./"))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?,
+    };
+    let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension);
+    let file_type = FileType::try_from(extension)?;
+    let address = match file_type {
+        FileType::Bin => default_load_address(vid, TransferTarget::Flash).unwrap_or(0),
+        _ => 0,
+    };
+
+    let data = std::fs::read(path)?;
+    upload_file(
+        brain,
+        TransferTarget::Flash,
+        file_type,
+        vid,
+        &data,
+        &name,
+        address,
+        CRC32.checksum(&data),
+        true,
+        SystemTime::now(),
+        FILE_VERSION,
+        None,
+        UploadAction::Nothing,
+    )
+    .await
+}
+
+/// Value parser for file names on the brain. The filesystem is flat, but names may contain `/`
+/// to group files into pseudo-directories, which `ls_files --tree` displays as a tree.
+fn parse_remote_name(name: &str) -> Result<String, String> {
+    let name = crate::parse_name(&name.replace('\\', "/"))?;
+    if name.len() >= NAME_LEN {
+        return Err(format!(
+            "`{}` is too long (at most {} characters, including directories)",
+            name,
+            NAME_LEN - 1
+        ));
+    }
+    if name.split('/').any(str::is_empty) {
+        return Err(format!(
+            "`{}` contains an empty directory or file name",
+            name
+        ));
+    }
+    let file_name = name.rsplit('/').next().expect("file name");
+    let has_extension = file_name
+        .rsplit_once('.')
+        .is_some_and(|(_, extension)| FileType::try_from(extension).is_ok());
+    if !has_extension {
+        return Err(format!(
+            "`{}` needs a file extension of at most 3 characters",
+            name
+        ));
+    }
+    Ok(name)
+}
+
+async fn export_ini(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    let slot = *args.get_one::<u8>(SLOT).expect("missing slot");
+    let path = args.get_one::<String>(PATH).expect("missing path");
+    let name = slot_file_name(slot, "ini");
+
+    let Some(metadata) = brain
+        .find_file_metadata(vid, FileFlags::empty(), &name)
+        .await?
+    else {
+        println!("slot {} does not have a program", slot);
+        return Ok(());
+    };
+    let download = brain.download_file_verified(&metadata).await?;
+    warn_crc_mismatch(&name, &download);
+    std::fs::write(path, download.data)?;
     Ok(())
 }
+
+async fn import_ini(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let vid = Vid::from(*args.get_one::<u8>(VID).expect("missing VID"));
+    let slot = *args.get_one::<u8>(SLOT).expect("missing slot");
+    let path = args.get_one::<String>(PATH).expect("missing path");
+
+    let data = std::fs::read(path)?;
+    // catch editing mistakes before they replace a working ini
+    let text = std::str::from_utf8(&data)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    Ini::load_from_str(text)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+    upload_file(
+        brain,
+        TransferTarget::Flash,
+        FileType::Ini,
+        vid,
+        &data,
+        &slot_file_name(slot, "ini"),
+        0,
+        CRC32.checksum(&data),
+        true,
+        SystemTime::now(),
+        FILE_VERSION,
+        None,
+        UploadAction::Nothing,
+    )
+    .await
+}
+
+/// The largest file [`gunzip`] will produce: all of user program memory.
+const MAX_DECOMPRESSED_SIZE: usize = 0x04800000;
+/// Deflate can't expand data by more than this, which bounds the size a file can claim.
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+enum GunzipError {
+    NotGzip,
+    Corrupt,
+}
+
+/// Decompresses gzip data, as programs are compressed when uploaded.
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, GunzipError> {
+    if !data.starts_with(&[0x1f, 0x8b]) || data.len() < 18 {
+        return Err(GunzipError::NotGzip);
+    }
+    // the gzip trailer ends with the uncompressed size, which can't be trusted to allocate
+    let size = u32::from_le_bytes(data[data.len() - 4..].try_into().expect("4 bytes")) as usize;
+    if size > MAX_DECOMPRESSED_SIZE.min(data.len() * MAX_DEFLATE_RATIO) {
+        return Err(GunzipError::Corrupt);
+    }
+    let mut decompressed = vec![0; size];
+    let len = Decompressor::new()
+        .gzip_decompress(data, &mut decompressed)
+        .map_err(|_| GunzipError::Corrupt)?;
+    decompressed.truncate(len);
+    Ok(decompressed)
+}
+
+async fn dump_flash(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let directory = PathBuf::from(
+        args.get_one::<String>(DIRECTORY)
+            .expect("missing directory"),
+    );
+    let quiet = args.get_flag(crate::QUIET);
+
+    let mut manifest = Vec::new();
+    for vid in args.get_many::<u8>(VID).expect("missing VID") {
+        let files = brain
+            .list_files(Vid::from(*vid), FileFlags::empty())
+            .await?;
+
+        for meta in files {
+            if FileType::try_from(meta.file_type.as_str()).is_err() {
+                println!(
+                    "{} skipping {} (unsupported file type `{}`)",
+                    yellow("Warning:"),
+                    meta.name,
+                    meta.file_type
+                );
+                continue;
+            }
+            let download = brain.download_file_verified(&meta).await?;
+            warn_crc_mismatch(&meta.name, &download);
+            let data = download.data;
+
+            // files are grouped by VID, as the same name can be used in several of them
+            let relative_path = format!("{}/{}", vid, meta.name);
+            let path = directory.join(&relative_path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, &data)?;
+            if !quiet {
+                println!("{}", path.display());
+            }
+
+            manifest.push(serde_json::json!({
+                "path": relative_path,
+                "name": meta.name,
+                "vid": vid,
+                "size": meta.size,
+                "addr": meta.addr,
+                "crc": meta.crc,
+                "file_type": meta.file_type,
+                "version": meta.version,
+                "timestamp": meta
+                    .timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |timestamp| timestamp.as_secs()),
+            }));
+        }
+    }
+
+    std::fs::create_dir_all(&directory)?;
+    let manifest = serde_json::json!({ "files": manifest });
+    std::fs::write(
+        directory.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest).expect("serialize manifest"),
+    )?;
+    Ok(())
+}
+
+struct DumpedFile {
+    path: String,
+    name: String,
+    vid: Vid,
+    addr: u32,
+    file_type: FileType,
+    timestamp: SystemTime,
+    version: u32,
+}
+
+async fn restore(brain: &mut Brain, args: &ArgMatches) -> Result<(), CommandError> {
+    let directory = PathBuf::from(
+        args.get_one::<String>(DIRECTORY)
+            .expect("missing directory"),
+    );
+    let quiet = args.get_flag(crate::QUIET);
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(directory.join("manifest.json"))?)
+            .map_err(std::io::Error::from)?;
+
+    let mut files = Vec::new();
+    for file in manifest["files"]
+        .as_array()
+        .ok_or(ParseError::MissingKey("files"))?
+    {
+        let string = |key: &'static str| file[key].as_str().ok_or(ParseError::MissingKey(key));
+        let number = |key: &'static str| file[key].as_u64().ok_or(ParseError::MissingKey(key));
+        let vid = Vid::from(number("vid")? as u8);
+        files.push(DumpedFile {
+            path: string("path")?.to_string(),
+            name: string("name")?.to_string(),
+            vid,
+            // hand-written manifests may leave out the address of files that use the usual one
+            addr: match file["addr"].as_u64() {
+                Some(addr) => addr as u32,
+                None => default_load_address(vid, TransferTarget::Flash)
+                    .ok_or(ParseError::MissingKey("addr"))?,
+            },
+            file_type: FileType::try_from(string("file_type")?)?,
+            timestamp: UNIX_EPOCH + Duration::from_secs(number("timestamp")?),
+            version: file["version"]
+                .as_u64()
+                .map_or(FILE_VERSION, |version| version as u32),
+        });
+    }
+    // cold packages must be on the brain before the programs that link to them
+    files.sort_by_key(|file| !matches!(file.vid, Vid::Pros));
+
+    // the brain does not report which cold package a program links to,
+    // so programs can only be re-linked when there is a single candidate
+    let cold_packages: Vec<String> = files
+        .iter()
+        .filter(|file| matches!((file.vid, &file.file_type), (Vid::Pros, FileType::Bin)))
+        .map(|file| file.name.clone())
+        .collect();
+    if cold_packages.len() > 1 {
+        println!(
+            "{} found {} cold packages, programs will not be linked to them",
+            yellow("Warning:"),
+            cold_packages.len()
+        );
+    }
+
+    for file in files {
+        let data = std::fs::read(directory.join(&file.path))?;
+        let link = match (file.vid, &file.file_type, &cold_packages[..]) {
+            (Vid::User, FileType::Bin, [cold_package]) => Some((cold_package.as_str(), Vid::Pros)),
+            _ => None,
+        };
+        upload_file(
+            brain,
+            TransferTarget::Flash,
+            file.file_type,
+            file.vid,
+            &data,
+            &file.name,
+            file.addr,
+            CRC32.checksum(&data),
+            true,
+            file.timestamp,
+            file.version,
+            link,
+            UploadAction::Nothing,
+        )
+        .await?;
+        if !quiet {
+            println!("{}", file.name);
+        }
+    }
+    Ok(())
+}
+
+fn parse_region(region: &str) -> Result<(u16, u16, u16, u16), String> {
+    let values = region
+        .split(',')
+        .map(|value| value.trim().parse::<u16>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("`{}` is not a valid region", region))?;
+    if let [x, y, width, height] = values[..] {
+        if width == 0
+            || height == 0
+            || x as u32 + width as u32 > SCREEN_WIDTH as u32
+            || y as u32 + height as u32 > SCREEN_HEIGHT as u32
+        {
+            return Err(format!(
+                "region must be non-empty and fit within the {}x{} screen",
+                SCREEN_WIDTH, SCREEN_HEIGHT
+            ));
+        }
+        Ok((x, y, width, height))
+    } else {
+        Err("region must be given as x,y,width,height".to_string())
+    }
+}
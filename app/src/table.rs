@@ -0,0 +1,70 @@
+pub(crate) struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub(crate) fn new(headers: Vec<&'static str>) -> Self {
+        Self {
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add_row(&mut self, row: Vec<String>) {
+        assert_eq!(row.len(), self.headers.len());
+        self.rows.push(row);
+    }
+
+    pub(crate) fn print(&self) {
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| header.len()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let separator = widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<_>>()
+            .join("+");
+        println!("+{}+", separator);
+        print_row(&widths, self.headers.iter().copied());
+        println!("+{}+", separator);
+        for row in &self.rows {
+            print_row(&widths, row.iter().map(String::as_str));
+        }
+        println!("+{}+", separator);
+    }
+
+    pub(crate) fn print_csv(&self) {
+        println!("{}", csv_row(self.headers.iter().copied()));
+        for row in &self.rows {
+            println!("{}", csv_row(row.iter().map(String::as_str)));
+        }
+    }
+}
+
+fn csv_row<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn print_row<'a>(widths: &[usize], cells: impl Iterator<Item = &'a str>) {
+    let cells = widths
+        .iter()
+        .zip(cells)
+        .map(|(width, cell)| format!(" {:<width$} ", cell, width = width))
+        .collect::<Vec<_>>()
+        .join("|");
+    println!("|{}|", cells);
+}
@@ -2,11 +2,13 @@ use clap::{Arg, ArgAction, ArgMatches, Command};
 use corncobs::CobsError;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use v5_serial::brain::Brain;
 use v5_serial::connection::RobotConnectionOptions;
 use v5_serial::error::{CommandError, CommunicationError};
 
 pub(crate) const COMMAND: &str = "terminal";
 const RAW_MODE: &str = "raw";
+const STOP_ON_EXIT: &str = "stop-on-exit";
 
 pub(crate) fn command() -> Command {
     Command::new(COMMAND)
@@ -15,6 +17,10 @@ pub(crate) fn command() -> Command {
             .help("Disables COBS encoding")
             .short('r')
             .action(ArgAction::SetTrue))
+        .arg(Arg::new(STOP_ON_EXIT)
+            .help("Stops the program running on the brain when the terminal is closed with Ctrl+C")
+            .long(STOP_ON_EXIT)
+            .action(ArgAction::SetTrue))
 }
 
 pub(crate) async fn terminal(
@@ -24,8 +30,28 @@ pub(crate) async fn terminal(
 ) -> Result<(), CommandError> {
     let raw = args.get_flag(RAW_MODE);
     let mut brain = v5_serial::connection::connect_to_brain(options).await?;
-    println!("Connected to brain");
+    crate::report_connection(&mut brain, args.get_flag(crate::QUIET)).await?;
+    attach(&mut brain, raw, args.get_flag(STOP_ON_EXIT)).await
+}
+
+/// Streams user serial until interrupted with Ctrl+C, then optionally stops the running program.
+pub(crate) async fn attach(
+    brain: &mut Brain,
+    raw: bool,
+    stop_on_exit: bool,
+) -> Result<(), CommandError> {
+    tokio::select! {
+        result = forward(brain, raw) => result?,
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    if stop_on_exit {
+        brain.stop_program().await?;
+    }
+    Ok(())
+}
 
+/// Forwards stdin to the brain's user serial and its output to stdout/stderr.
+async fn forward(brain: &mut Brain, raw: bool) -> Result<(), CommandError> {
     let mut stdin = tokio::io::stdin();
     loop {
         let mut in_buf = [0_u8; 256];
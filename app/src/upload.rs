@@ -1,44 +1,65 @@
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 
 use base64::Engine;
-use clap::{Arg, ArgAction, ArgMatches, Command, value_parser, ValueHint};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
 use clap::builder::NonEmptyStringValueParser;
-use crc::{Algorithm, Crc};
+use clap::parser::ValueSource;
 use ini::Ini;
 use libdeflater::{CompressionLvl, Compressor};
 use log::{debug, warn};
+use object::{Object, ObjectSegment};
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
 use v5_serial::brain::Brain;
 use v5_serial::brain::filesystem::{
-    FileFlags, FileType, TransferDirection, TransferTarget, UploadAction, Vid,
+    default_load_address, DeleteFlags, FileFlags, FileTransfer, FileType, TransferDirection,
+    TransferTarget, UploadAction, Vid, CRC32,
 };
 use v5_serial::connection::{Nack, RobotConnectionOptions};
 use v5_serial::error::{CommandError, CommunicationError};
 
-pub const CRC32: Crc<u32> = Crc::<u32>::new(&Algorithm {
-    width: 32,
-    poly: 0x04C11DB7,
-    init: 0,
-    refin: false,
-    refout: false,
-    xorout: 0,
-    check: 0x89A1897F,
-    residue: 0,
-});
+/// How many times a transfer is started before giving up, if the brain loses track of it.
+const MAX_TRANSFER_ATTEMPTS: u32 = 3;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Memory reserved for user programs on the brain. Packages loaded outside of it will not run.
+const USER_PROGRAM_MEMORY: Range<u32> = 0x03800000..0x08000000;
+
+/// Written to the version field of uploaded files' metadata (shown by `manage ls_files`).
+/// It is informational only: compression is detected from the file contents.
+pub(crate) const FILE_VERSION: u32 = 0b00_01_00;
 
 pub(crate) const COMMAND: &str = "upload";
 const COLD_PACKAGE: &str = "cold";
 const HOT_PACKAGE: &str = "hot";
 const COLD_ADDRESS: &str = "cold-address";
 const HOT_ADDRESS: &str = "hot-address";
+const ADDRESS_AUTO: &str = "address-auto";
 const NAME: &str = "name";
 const DESCRIPTION: &str = "description";
 const INDEX: &str = "index";
 const ACTION: &str = "action";
+const AFTER_UPLOAD: &str = "after-upload";
+const PRINT_COLD_NAME: &str = "print-cold-name";
+const COLD_NAME: &str = "cold-name";
+const FORMAT: &str = "format";
+const BENCH: &str = "bench";
+const STOP_RUNNING: &str = "stop-running";
+const KEEP_COLD: &str = "keep-cold";
+const OVERWRITE: &str = "overwrite";
+const NO_OVERWRITE: &str = "no-overwrite";
+const RUN_AFTER: &str = "run-after";
+const STRIP: &str = "strip";
+const INI_ONLY: &str = "ini-only";
+
+/// With --keep-cold, the cold package is assumed to still be on the brain for this many uploads
+/// before it is checked again, in case it was deleted or a different brain is connected.
+const KEEP_COLD_RECHECK: u32 = 10;
 
 pub(crate) fn command() -> Command {
     Command::new(COMMAND)
@@ -63,22 +84,27 @@ pub(crate) fn command() -> Command {
         )
         .arg(
             Arg::new(COLD_ADDRESS)
-                .help("Starting memory address of the cold package binary")
-                .default_value("0x03800000")
+                .help("Starting memory address of the cold package binary [default: 0x03800000]")
+                .value_parser(parse_address)
                 .action(ArgAction::Set),
         )
         .arg(
             Arg::new(HOT_ADDRESS)
-                .help("Starting memory address of the hot package binary")
-                .default_value("0x07800000")
+                .help("Starting memory address of the hot package binary [default: 0x07800000]")
+                .value_parser(parse_address)
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new(ADDRESS_AUTO)
+                .long(ADDRESS_AUTO)
+                .help("Loads the hot package at the address of the program it replaces, unless a hot address is given")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new(NAME)
                 .short('n')
-                .help("Name of the program when uploading")
-                .default_value("Program")
-                .value_parser(NonEmptyStringValueParser::new())
+                .help("Name of the program when uploading (defaults to the hot package file name)")
+                .value_parser(crate::parse_name)
                 .action(ArgAction::Set),
         )
         .arg(
@@ -92,19 +118,105 @@ pub(crate) fn command() -> Command {
         .arg(
             Arg::new(INDEX)
                 .short('i')
-                .help("What slot to install the program into (1-8)")
-                .value_parser(value_parser!(u8).range(1..=8))
+                .help("What slot(s) to install the program into (1-8), e.g. 1 or 1,3,5 or 2-4")
+                .value_parser(parse_slots)
                 .default_value("1")
                 .action(ArgAction::Set),
         )
         .arg(
             Arg::new(ACTION)
                 .short('a')
+                .long(AFTER_UPLOAD)
                 .help("What to do after uploading the program")
                 .value_parser(["nothing", "run", "screen"])
                 .default_value("screen")
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new(OVERWRITE)
+                .long(OVERWRITE)
+                .help("Replace programs already installed in the slots (the default)")
+                .overrides_with(NO_OVERWRITE)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(NO_OVERWRITE)
+                .long(NO_OVERWRITE)
+                .help("Fail instead of replacing a program already installed in a slot")
+                .overrides_with(OVERWRITE)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(RUN_AFTER)
+                .long(RUN_AFTER)
+                .help("Runs the program after uploading it and opens the terminal to show its output")
+                .conflicts_with(ACTION)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(COLD_NAME)
+                .long(COLD_NAME)
+                .help("Use an existing cold package (e.g. one installed by PROS) by name")
+                .value_parser(crate::parse_name)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new(FORMAT)
+                .long(FORMAT)
+                .help("How to package the binaries before uploading them")
+                .value_parser(["gzip", "raw"])
+                .default_value("gzip")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new(INI_ONLY)
+                .long(INI_ONLY)
+                .help("Only updates the name, description and icon of the programs in the slots, leaving the programs themselves untouched")
+                .conflicts_with_all([
+                    RUN_AFTER,
+                    STRIP,
+                    KEEP_COLD,
+                    COLD_NAME,
+                    STOP_RUNNING,
+                    BENCH,
+                    NO_OVERWRITE,
+                ])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(STRIP)
+                .long(STRIP)
+                .help(
+                    "Converts packages that are ELF files into raw binaries before uploading \
+                     them, loaded at the address they were linked at",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(PRINT_COLD_NAME)
+                .long(PRINT_COLD_NAME)
+                .help("Prints the remote name of the cold package and exits without uploading")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(KEEP_COLD)
+                .long(KEEP_COLD)
+                .help("Skips checking the brain for a cold package this computer recently confirmed was uploaded")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(STOP_RUNNING)
+                .long(STOP_RUNNING)
+                .help("Stops any running program before uploading, as the brain may reject the upload otherwise")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(BENCH)
+                .long(BENCH)
+                .help("Reports the throughput of the upload")
+                .hide(true)
+                .action(ArgAction::SetTrue),
+        )
 }
 
 pub(crate) async fn upload(
@@ -112,7 +224,10 @@ pub(crate) async fn upload(
     args: ArgMatches,
     options: RobotConnectionOptions,
 ) -> Result<(), CommandError> {
-    let program_name = args.get_one::<String>(NAME).expect("program name");
+    let program_name = match args.get_one::<String>(NAME) {
+        Some(name) => name.clone(),
+        None => program_name_from_path(&args),
+    };
     let description = args.get_one::<String>(DESCRIPTION).expect("description");
     let cold_package_path = args
         .get_one::<String>(COLD_PACKAGE)
@@ -122,134 +237,564 @@ pub(crate) async fn upload(
         .get_one::<String>(HOT_PACKAGE)
         .expect("hot package path")
         .clone();
-    let cold_address = u32::from_str_radix(
-        args.get_one::<String>(COLD_ADDRESS)
-            .expect("cold address")
-            .replace("0x", "")
-            .as_str(),
-        16,
-    )
-    .expect("");
-    let hot_address = u32::from_str_radix(
-        args.get_one::<String>(HOT_ADDRESS)
-            .expect("hot address")
-            .replace("0x", "")
-            .as_str(),
-        16,
-    )
-    .expect("hot address to be hex number");
+    let address = |id: &str, vid: Vid| {
+        args.get_one::<u32>(id).copied().unwrap_or_else(|| {
+            default_load_address(vid, TransferTarget::Flash).expect("default load address")
+        })
+    };
+    let cold_address = address(COLD_ADDRESS, Vid::Pros);
+    let hot_address = address(HOT_ADDRESS, Vid::User);
+    let auto_address = args.get_flag(ADDRESS_AUTO) && args.get_one::<u32>(HOT_ADDRESS).is_none();
+    for (package, address) in [("cold", cold_address), ("hot", hot_address)] {
+        if !USER_PROGRAM_MEMORY.contains(&address) {
+            println!(
+                "{} {} package address {:#010x} is outside of user program memory ({:#010x}-{:#010x})",
+                crate::color::yellow("Warning:"),
+                package,
+                address,
+                USER_PROGRAM_MEMORY.start,
+                USER_PROGRAM_MEMORY.end
+            );
+        }
+    }
     let action = args.get_one::<String>(ACTION).expect("action");
-    let overwrite = true;
-    let index = *args.get_one::<u8>(INDEX).expect("slot index") - 1;
+    let overwrite = !args.get_flag(NO_OVERWRITE);
+    let slots = args.get_one::<Vec<u8>>(INDEX).expect("slot index");
     let timestamp = SystemTime::now();
-    let file_name = format!("slot_{}.bin", index);
-    let file_ini = format!("slot_{}.ini", index);
-    let action = UploadAction::try_from(action.as_str())?;
+    let action = if args.get_flag(RUN_AFTER) {
+        UploadAction::Run
+    } else {
+        UploadAction::try_from(action.as_str())?
+    };
 
-    let brain = tokio::task::spawn(v5_serial::connection::connect_to_brain(options));
-    let cold_handle = tokio::task::spawn(load_compressed(cold_package_path)); //probably overkill
-    let hot_handle = tokio::task::spawn(load_compressed(hot_package_path));
+    let cold_name = args.get_one::<String>(COLD_NAME);
+    let compress = args.get_one::<String>(FORMAT).expect("format") == "gzip";
+    let strip = args.get_flag(STRIP);
 
-    let ini = generate_program_ini(
-        "0.1.0",
-        "PROS",
-        program_name,
-        "0.1.0",
-        index,
-        "USER902x.bmp",
-        description,
-        timestamp,
-    )
-    .await;
+    if args.get_flag(PRINT_COLD_NAME) {
+        let (cold_package, _) = load_package(cold_package_path, compress, strip).await?;
+        println!(
+            "{}",
+            cold_name
+                .cloned()
+                .unwrap_or_else(|| get_cold_package_name(&cold_package))
+        );
+        return Ok(());
+    }
 
-    let cold_package = cold_handle.await.expect("join task")?;
-    let cold_hash = base64::engine::general_purpose::STANDARD
-        .encode(extendhash::md5::compute_hash(cold_package.as_slice()));
+    if args.get_flag(INI_ONLY) {
+        let mut brain = v5_serial::connection::connect_to_brain(options).await?;
+        crate::report_connection(&mut brain, args.get_flag(crate::QUIET)).await?;
+        for slot in slots {
+            upload_slot_ini(
+                &mut brain,
+                &program_name,
+                description,
+                *slot,
+                overwrite,
+                timestamp,
+            )
+            .await?;
+        }
+        return Ok(());
+    }
+
+    let brain = tokio::task::spawn(v5_serial::connection::connect_to_brain(options));
+    let cold_handle = tokio::task::spawn(load_package(cold_package_path, compress, strip)); //probably overkill
+    let hot_handle = tokio::task::spawn(load_package(hot_package_path, compress, strip));
+
+    let (cold_package, cold_linked) = cold_handle.await.expect("join task")?;
+    let cold_address = load_address(
+        "cold",
+        cold_linked,
+        cold_address,
+        args.get_one::<u32>(COLD_ADDRESS).is_some(),
+    )?;
     let cold_len = cold_package.len();
     let crc = CRC32.checksum(&cold_package);
-    let cold_package_name = &cold_hash[..22];
+    let mut cold_package_name = cold_name
+        .cloned()
+        .unwrap_or_else(|| get_cold_package_name(&cold_package));
 
     let mut skip_cold = false;
 
     let mut brain = brain.await.expect("join task")?;
-    let available_package = brain
-        .get_file_metadata_by_name(Vid::Pros, FileFlags::empty(), cold_package_name)
-        .await;
+    crate::report_connection(&mut brain, args.get_flag(crate::QUIET)).await?;
+    let start = (Instant::now(), brain.stats());
+    if !overwrite {
+        // checked up front so that nothing is uploaded if any of the slots are taken
+        for slot in slots {
+            let bin = brain
+                .find_file_metadata(Vid::User, FileFlags::empty(), &slot_file_name(*slot, "bin"))
+                .await?;
+            if bin.is_some() {
+                return Err(CommandError::SlotOccupied(*slot));
+            }
+        }
+    }
+    if args.get_flag(STOP_RUNNING) {
+        brain.stop_program().await?;
+    }
+    let keep_cold = args.get_flag(KEEP_COLD);
+    let mut trusted_marker = false;
+    if let Some((name, uses)) = keep_cold.then(|| read_cold_marker(crc)).flatten() {
+        if uses < KEEP_COLD_RECHECK && !cold_name.is_some_and(|cold_name| *cold_name != name) {
+            debug!("assuming cold package {} is still on the brain", name);
+            write_cold_marker(crc, &name, uses + 1);
+            cold_package_name = name;
+            skip_cold = true;
+            trusted_marker = true;
+        }
+    }
 
-    match available_package {
-        Ok(package) => {
+    if !skip_cold {
+        let available_package = brain
+            .find_file_metadata(Vid::Pros, FileFlags::empty(), &cold_package_name)
+            .await?;
+        if let Some(package) = available_package {
             if package.size == cold_len as u32 && package.crc == crc {
                 skip_cold = true;
             }
         }
-        Err(err) => match err {
-            CommunicationError::NegativeAcknowledgement(nack) => match nack {
-                Nack::ProgramFileError => {}
-                _ => return Err(err.into()),
-            },
-            _ => return Err(err.into()),
-        },
+    }
+
+    if !skip_cold && cold_name.is_none() {
+        // the cold package may have been installed by PROS under a different name
+        if let Some(name) = find_cold_package(&mut brain, cold_len as u32, crc).await? {
+            debug!("reusing existing cold package {}", name);
+            cold_package_name = name;
+            skip_cold = true;
+        }
     }
 
     if !skip_cold {
-        println!("Cold package does not match. Re-uploading...");
+        if !args.get_flag(crate::QUIET) {
+            println!(
+                "{}",
+                crate::color::yellow("Cold package does not match. Re-uploading...")
+            );
+        }
         upload_file(
             &mut brain,
             TransferTarget::Flash,
             FileType::Bin,
             Vid::Pros,
             &cold_package,
-            cold_package_name,
+            &cold_package_name,
             cold_address,
             crc,
             overwrite,
             timestamp,
+            FILE_VERSION,
             None,
             UploadAction::Nothing,
         )
         .await?;
     }
+    if keep_cold && !trusted_marker {
+        write_cold_marker(crc, &cold_package_name, 0);
+    }
 
-    let hot_package = hot_handle.await.expect("join task")?;
-    let crc = CRC32.checksum(&hot_package);
-    upload_file(
-        &mut brain,
-        TransferTarget::Flash,
-        FileType::Bin,
-        Vid::User,
-        &hot_package,
-        &file_name,
+    let (hot_package, hot_linked) = hot_handle.await.expect("join task")?;
+    let hot_address = load_address(
+        "hot",
+        hot_linked,
         hot_address,
-        crc,
-        overwrite,
+        args.get_one::<u32>(HOT_ADDRESS).is_some(),
+    )?;
+    let hot_crc = CRC32.checksum(&hot_package);
+    for (i, slot) in slots.iter().enumerate() {
+        let bin_name = slot_file_name(*slot, "bin");
+        // a stripped package only runs where it was linked
+        let address = if auto_address && hot_linked.is_none() {
+            existing_address(&mut brain, &bin_name)
+                .await?
+                .unwrap_or(hot_address)
+        } else {
+            hot_address
+        };
+        // the ini goes first so the program is complete when the binary finishes uploading,
+        // which is when the brain applies the action
+        upload_slot_ini(
+            &mut brain,
+            &program_name,
+            description,
+            *slot,
+            overwrite,
+            timestamp,
+        )
+        .await?;
+
+        upload_file(
+            &mut brain,
+            TransferTarget::Flash,
+            FileType::Bin,
+            Vid::User,
+            &hot_package,
+            &bin_name,
+            address,
+            hot_crc,
+            overwrite,
+            timestamp,
+            FILE_VERSION,
+            Some((cold_package_name.as_str(), Vid::Pros)),
+            // only act on the last slot, otherwise each upload would restart the program
+            if i == slots.len() - 1 {
+                action
+            } else {
+                UploadAction::Nothing
+            },
+        )
+        .await
+        .map_err(|err| slot_occupied(err, *slot))?;
+    }
+
+    if args.get_flag(BENCH) {
+        let (start_time, start_stats) = start;
+        let elapsed = start_time.elapsed();
+        let stats = brain.stats();
+        let bytes = stats.bytes_sent - start_stats.bytes_sent;
+        println!(
+            "{} bytes in {:.2?} ({:.1} KB/s), {} retransmissions, max packet size {}",
+            bytes,
+            elapsed,
+            bytes as f64 / 1024.0 / elapsed.as_secs_f64(),
+            stats.retransmissions - start_stats.retransmissions,
+            brain.connection.get_max_packet_size()
+        );
+    }
+
+    if !args.get_flag(crate::QUIET) {
+        // retransmissions are a sign of a poor connection, e.g. a weak wireless link
+        let retransmissions = brain.stats().retransmissions;
+        let message = format!("Upload completed with {} retransmissions", retransmissions);
+        if retransmissions > 0 {
+            println!("{}", crate::color::yellow(message));
+        } else {
+            println!("{}", crate::color::green(message));
+        }
+    }
+    if args.get_flag(RUN_AFTER) {
+        crate::terminal::attach(&mut brain, false, false).await?;
+    }
+    Ok(())
+}
+
+/// Writes the whole file (and its link) to an initialized transfer.
+async fn write_transfer(
+    transfer: &mut FileTransfer<'_>,
+    file: &[u8],
+    remote_name: &str,
+    address: u32,
+    linked_file: Option<(&str, Vid)>,
+) -> Result<(), CommunicationError> {
+    let chunk_size = transfer.chunk_size() as usize;
+    debug!(
+        "writing {} in chunks of {} bytes (brain allows {})",
+        remote_name, chunk_size, transfer.parameters.max_packet_size
+    );
+    if let Some((name, vid)) = linked_file {
+        transfer.set_link(name, vid).await?;
+    }
+    for i in (0..file.len()).step_by(chunk_size) {
+        let end = file.len().min(i + chunk_size);
+        transfer.write(&file[i..end], address + i as u32).await?;
+    }
+    Ok(())
+}
+
+/// The name of a slot's (1-8) program or ini file. Files are numbered from 0, so slot 1 is
+/// `slot_0.bin`.
+pub(crate) fn slot_file_name(slot: u8, extension: &str) -> String {
+    format!("slot_{}.{}", slot - 1, extension)
+}
+
+/// Generates and uploads the ini describing the program in a slot (1-8).
+async fn upload_slot_ini(
+    brain: &mut Brain,
+    program_name: &str,
+    description: &str,
+    slot: u8,
+    overwrite: bool,
+    timestamp: SystemTime,
+) -> Result<(), CommandError> {
+    let index = slot - 1;
+    let conf = generate_program_ini(
+        "0.1.0",
+        "PROS",
+        program_name,
+        "0.1.0",
+        index,
+        "USER902x.bmp",
+        description,
         timestamp,
-        Some((cold_package_name, Vid::Pros)),
-        UploadAction::Nothing,
     )
-    .await?;
-
-    let conf = ini;
+    .await;
     let crc = CRC32.checksum(&conf);
     upload_file(
-        &mut brain,
+        brain,
         TransferTarget::Flash,
         FileType::Ini,
         Vid::User,
         &conf,
-        &file_ini,
+        &slot_file_name(slot, "ini"),
         0,
         crc,
         overwrite,
         timestamp,
+        FILE_VERSION,
         None,
-        action,
+        UploadAction::Nothing,
     )
-    .await?;
-    Ok(())
+    .await
+    .map_err(|err| slot_occupied(err, slot))
+}
+
+/// The brain refuses to replace a file when overwriting is disabled, which means the slot is taken.
+fn slot_occupied(err: CommandError, slot: u8) -> CommandError {
+    match err {
+        CommandError::CommunicationError(CommunicationError::NegativeAcknowledgement(
+            Nack::FileExists,
+        )) => CommandError::SlotOccupied(slot),
+        err => err,
+    }
+}
+
+/// Names the program after the hot package (e.g. `bin/autonskills.bin` becomes `autonskills`).
+/// The default package path would only give `hot.package`, so it keeps the generic name.
+fn program_name_from_path(args: &ArgMatches) -> String {
+    if args.value_source(HOT_PACKAGE) != Some(ValueSource::CommandLine) {
+        return "Program".to_string();
+    }
+    let path = args
+        .get_one::<String>(HOT_PACKAGE)
+        .expect("hot package path");
+    Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty() && stem.is_ascii())
+        .unwrap_or("Program")
+        .to_string()
+}
+
+fn cold_marker_path(crc: u32) -> Option<PathBuf> {
+    Some(crate::config::cache_dir()?.join(format!("cold_{:08x}", crc)))
+}
+
+/// Reads the name of a cold package known to be on the brain, and how many uploads have trusted
+/// it since it was last checked.
+fn read_cold_marker(crc: u32) -> Option<(String, u32)> {
+    let marker = std::fs::read_to_string(cold_marker_path(crc)?).ok()?;
+    let (name, uses) = marker.trim().split_once('\n')?;
+    Some((name.to_string(), uses.parse().ok()?))
+}
+
+fn write_cold_marker(crc: u32, name: &str, uses: u32) {
+    let Some(path) = cold_marker_path(crc) else {
+        return;
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, format!("{}\n{}\n", name, uses)));
+    if let Err(err) = result {
+        warn!("failed to write {}: {}", path.display(), err);
+    }
+}
+
+/// The remote name of a (compressed) cold package: its truncated base64 md5 hash.
+fn get_cold_package_name(cold_package: &[u8]) -> String {
+    let cold_hash = base64::engine::general_purpose::STANDARD
+        .encode(extendhash::md5::compute_hash(cold_package));
+    cold_hash[..22].to_string()
+}
+
+/// The load address of the program currently in a slot, so that a program built for a
+/// non-standard address is not relocated when it is overwritten.
+async fn existing_address(
+    brain: &mut Brain,
+    name: &str,
+) -> Result<Option<u32>, CommunicationError> {
+    let Some(existing) = brain
+        .find_file_metadata(Vid::User, FileFlags::empty(), name)
+        .await?
+    else {
+        return Ok(None);
+    };
+    if USER_PROGRAM_MEMORY.contains(&existing.addr) {
+        debug!("reusing address {:#010x} of {}", existing.addr, name);
+        Ok(Some(existing.addr))
+    } else {
+        warn!(
+            "ignoring address {:#010x} of {} as it is outside of user program memory",
+            existing.addr, name
+        );
+        Ok(None)
+    }
 }
 
-async fn load_compressed<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<u8>> {
-    let input = std::fs::read(&path)?;
+async fn find_cold_package(
+    brain: &mut Brain,
+    size: u32,
+    crc: u32,
+) -> Result<Option<String>, CommunicationError> {
+    // the index order spans every VID, so only PROS files are searched
+    Ok(brain
+        .list_files(Vid::Pros, FileFlags::empty())
+        .await?
+        .into_iter()
+        .find(|meta| meta.size == size && meta.crc == crc)
+        .map(|meta| meta.name))
+}
+
+fn parse_slots(slots: &str) -> Result<Vec<u8>, String> {
+    let parse_slot = |slot: &str| match slot.trim().parse::<u8>() {
+        Ok(slot) if (1..=8).contains(&slot) => Ok(slot),
+        _ => Err(format!("`{}` is not a valid slot (1-8)", slot)),
+    };
+
+    let mut parsed = Vec::new();
+    for part in slots.split(',') {
+        let range = match part.split_once('-') {
+            Some((start, end)) => {
+                let range = parse_slot(start)?..=parse_slot(end)?;
+                if range.is_empty() {
+                    return Err(format!("`{}` is an empty slot range", part));
+                }
+                range
+            }
+            None => {
+                let slot = parse_slot(part)?;
+                slot..=slot
+            }
+        };
+        for slot in range {
+            if !parsed.contains(&slot) {
+                parsed.push(slot);
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_address(address: &str) -> Result<u32, String> {
+    let digits = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    u32::from_str_radix(digits, 16)
+        .map_err(|_| format!("`{}` is not a valid hexadecimal address", address))
+}
+
+/// Reads a package, converting it from an ELF file if `strip` is set and gzipping it unless it
+/// should be uploaded as is. The brain recognises gzipped files by their contents, so no transfer
+/// option changes. Also returns the address a stripped ELF file was linked at.
+async fn load_package(
+    path: String,
+    compress: bool,
+    strip: bool,
+) -> std::io::Result<(Vec<u8>, Option<u32>)> {
+    let mut input = std::fs::read(&path)?;
+    let mut linked = None;
+    if strip && input.starts_with(ELF_MAGIC) {
+        let (start, bin) = elf_to_bin(&input).map_err(|err| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to strip {}: {}", path, err),
+            )
+        })?;
+        input = bin;
+        linked = Some(start);
+    }
+    check_binary_format(Path::new(&path), &input);
+    if compress {
+        Ok((load_compressed(path, input).await?, linked))
+    } else {
+        Ok((input, linked))
+    }
+}
+
+/// Where to upload a package. One stripped from an ELF file only runs at the address it was linked
+/// at, which replaces the default address and has to match one given explicitly.
+fn load_address(
+    package: &str,
+    linked: Option<u32>,
+    address: u32,
+    explicit: bool,
+) -> Result<u32, CommandError> {
+    match linked {
+        Some(linked) if explicit && linked != address => Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "the {} package is linked at {:#010x}, but would be uploaded to {:#010x}",
+                package, linked, address
+            ),
+        )
+        .into()),
+        Some(linked) => Ok(linked),
+        None => Ok(address),
+    }
+}
+
+/// Lays out the loadable segments of an ELF file like `objcopy -O binary`: starting at the lowest
+/// address, with any gaps between segments filled with zeroes. Returns the address it starts at.
+fn elf_to_bin(elf: &[u8]) -> Result<(u32, Vec<u8>), String> {
+    let file = object::File::parse(elf).map_err(|err| err.to_string())?;
+    let mut segments = Vec::new();
+    for segment in file.segments() {
+        let data = segment.data().map_err(|err| err.to_string())?;
+        if !data.is_empty() {
+            segments.push((segment.address(), data));
+        }
+    }
+    let Some(start) = segments.iter().map(|(address, _)| *address).min() else {
+        return Err("it has no loadable segments".to_string());
+    };
+    let end = segments
+        .iter()
+        .map(|(address, data)| address + data.len() as u64)
+        .max()
+        .expect("segments");
+    if start < USER_PROGRAM_MEMORY.start as u64 || end > USER_PROGRAM_MEMORY.end as u64 {
+        return Err(format!(
+            "its segments ({:#010x}-{:#010x}) are outside of user program memory \
+             ({:#010x}-{:#010x})",
+            start, end, USER_PROGRAM_MEMORY.start, USER_PROGRAM_MEMORY.end
+        ));
+    }
+    debug!("stripped ELF loads at {:#010x}", start);
+
+    let mut bin = vec![0_u8; (end - start) as usize];
+    for (address, data) in segments {
+        let offset = (address - start) as usize;
+        bin[offset..offset + data.len()].copy_from_slice(data);
+    }
+    Ok((start as u32, bin))
+}
+
+/// Warns about files that are clearly not raw binaries, as they upload fine but never run.
+fn check_binary_format(path: &Path, input: &[u8]) {
+    let problem = if input.starts_with(ELF_MAGIC) {
+        "is an ELF file, upload the .bin produced from it instead or pass --strip"
+    } else if input.is_empty() {
+        "is empty"
+    } else if input[..input.len().min(512)]
+        .iter()
+        .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+    {
+        "looks like a text file, not a raw binary"
+    } else {
+        return;
+    };
+    println!(
+        "{} {} {}",
+        crate::color::yellow("Warning:"),
+        path.display(),
+        problem
+    );
+}
+
+async fn load_compressed<P: AsRef<Path>>(path: P, input: Vec<u8>) -> std::io::Result<Vec<u8>> {
     let input_hash = extendhash::sha256::compute_hash(&input);
     let path = path.as_ref();
     let cache = adjacent_file(path, "cache");
@@ -325,7 +870,7 @@ fn adjacent_file(path: &Path, extension: &'static str) -> PathBuf {
     path.with_extension(extension)
 }
 
-async fn upload_file(
+pub(crate) async fn upload_file(
     brain: &mut Brain,
     target: TransferTarget,
     file_type: FileType,
@@ -336,11 +881,10 @@ async fn upload_file(
     crc: u32,
     overwrite: bool,
     timestamp: SystemTime,
+    version: u32,
     linked_file: Option<(&str, Vid)>,
     action: UploadAction,
 ) -> Result<(), CommandError> {
-    let max_packet_size = brain.connection.get_max_packet_size();
-
     let mut transfer = brain
         .file_transfer_initialize(
             TransferDirection::Upload,
@@ -350,25 +894,69 @@ async fn upload_file(
             file.len() as u32,
             address,
             crc,
-            0b00_01_00,
+            version,
             file_type,
             remote_name,
             timestamp,
         )
         .await?;
     assert!(transfer.parameters.file_size >= file.len() as u32);
-    if let Some((name, vid)) = linked_file {
-        transfer.set_link(name, vid).await?;
+    if transfer.parameters.crc != crc {
+        // the brain echoes the CRC it will verify against, so a mismatch means the transfer was
+        // set up for a different file than the one being written
+        println!(
+            "{} brain expects CRC {:#010x} for {}, but it is {:#010x}",
+            crate::color::yellow("Warning:"),
+            transfer.parameters.crc,
+            remote_name,
+            crc
+        );
     }
-    let max_packet_size = max_packet_size.min(transfer.parameters.max_packet_size / 2) - 14;
+    let result = tokio::select! {
+        result = async {
+            let mut attempts = 1;
+            loop {
+                let written =
+                    write_transfer(&mut transfer, file, remote_name, address, linked_file).await;
+                match written {
+                    Err(CommunicationError::NegativeAcknowledgement(
+                        nack @ (Nack::UninitializedTransfer | Nack::InvalidInitialization),
+                    )) if attempts < MAX_TRANSFER_ATTEMPTS => {
+                        // usually a dropped initialization packet on a poor link
+                        warn!(
+                            "brain lost the transfer of {} ({}), starting over",
+                            remote_name, nack
+                        );
+                        attempts += 1;
+                        transfer.reinitialize().await?;
+                    }
+                    result => break result,
+                }
+            }?;
+            transfer.complete(action).await
+        } => result.map_err(CommandError::from),
+        _ = tokio::signal::ctrl_c() => Err(CommandError::IoError(std::io::Error::new(
+            ErrorKind::Interrupted,
+            "upload interrupted",
+        ))),
+    };
 
-    let max_packet_size = max_packet_size - (max_packet_size % 4); //4 byte alignment
-    for i in (0..file.len()).step_by(max_packet_size as usize) {
-        let end = file.len().min(i + max_packet_size as usize);
-        transfer.write(&file[i..end], address + i as u32).await?;
+    if result.is_err() {
+        if let Err(err) = brain.close_abandoned_transfer().await {
+            debug!("failed to close transfer: {}", err);
+        }
+        // the brain keeps the partially written entry around, which causes NACKs on later uploads
+        if let Err(err) = brain
+            .delete_file(vid, DeleteFlags::empty(), remote_name)
+            .await
+        {
+            warn!(
+                "failed to remove partially uploaded file {}: {}",
+                remote_name, err
+            );
+        }
     }
-    transfer.complete(action).await?;
-    Ok(())
+    result
 }
 
 async fn generate_program_ini(
@@ -401,5 +989,6 @@ async fn generate_program_ini(
     let mut conf = Vec::with_capacity(128);
     ini.write_to(&mut conf).expect("write ini");
     conf.shrink_to_fit();
+    debug!("generated program ini:\n{}", String::from_utf8_lossy(&conf));
     conf
 }
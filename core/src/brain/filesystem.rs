@@ -3,12 +3,42 @@ use std::mem::size_of;
 use std::time::SystemTime;
 
 use bitflags::{bitflags, Flags};
+use crc::{Algorithm, Crc};
 use log::debug;
 
-use crate::brain::Brain;
+use crate::brain::{check_name, Brain, NAME_LEN};
 use crate::brain::system::Channel;
 use crate::buffer::ReceivingBuffer;
-use crate::error::{CommunicationError, ParseError};
+use crate::connection::Nack;
+use crate::error::{CommandError, CommunicationError, ParseError};
+
+/// Bytes of a read or write packet that are not file data: the header, command, length,
+/// address and CRC.
+const TRANSFER_OVERHEAD: u16 = 14;
+
+/// The CRC the brain keeps in each file's metadata.
+pub const CRC32: Crc<u32> = Crc::<u32>::new(&Algorithm {
+    width: 32,
+    poly: 0x04C11DB7,
+    init: 0,
+    refin: false,
+    refout: false,
+    xorout: 0,
+    check: 0x89A1897F,
+    residue: 0,
+});
+
+/// How many times [`Brain::download_file_verified`] reads a file that doesn't match its CRC.
+pub const MAX_DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// The number of file bytes sent in each read or write packet. Only half of the size the brain
+/// reports is used, and chunks are kept 4-byte aligned as the brain requires.
+pub fn transfer_chunk_size(connection_max: u16, brain_max: u16) -> u16 {
+    let size = connection_max
+        .min(brain_max / 2)
+        .saturating_sub(TRANSFER_OVERHEAD);
+    (size - size % 4).max(4)
+}
 
 pub struct UploadParameters {
     pub max_packet_size: u16,
@@ -16,6 +46,15 @@ pub struct UploadParameters {
     pub crc: u32,
 }
 
+/// A file read by [`Brain::download_file_verified`].
+pub struct VerifiedDownload {
+    pub data: Vec<u8>,
+    /// Whether the data matches the CRC in the file's metadata.
+    pub crc_matches: bool,
+    /// How many times the file was read.
+    pub attempts: u32,
+}
+
 pub struct FileMetadata {
     pub vid: Vid,
     pub size: u32,
@@ -23,10 +62,50 @@ pub struct FileMetadata {
     pub crc: u32,
     pub file_type: String,
     pub timestamp: SystemTime,
+    /// Packed as described by [`FileVersion`], see [`FileMetadata::file_version`].
     pub version: u32,
     pub name: String,
 }
 
+impl FileMetadata {
+    pub fn file_version(&self) -> FileVersion {
+        FileVersion::from(self.version)
+    }
+}
+
+/// A file's version, packed into a `u32` as the major, minor, patch and build numbers from the
+/// most to the least significant byte, like the program version in a slot's ini
+/// (`16777216` is 1.0.0).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    pub build: u8,
+}
+
+impl From<u32> for FileVersion {
+    fn from(version: u32) -> Self {
+        let [major, minor, patch, build] = version.to_be_bytes();
+        Self {
+            major,
+            minor,
+            patch,
+            build,
+        }
+    }
+}
+
+impl Display for FileVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if self.build != 0 {
+            write!(f, "-{}", self.build)?;
+        }
+        Ok(())
+    }
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug)]
 pub enum UploadAction {
@@ -87,6 +166,17 @@ impl From<TransferTarget> for u8 {
     }
 }
 
+/// The address files are conventionally loaded at, or `None` if there is no convention for the
+/// given directory and target. PROS cold packages live at the start of user program memory and
+/// the hot packages that link to them are loaded after it.
+pub fn default_load_address(vid: Vid, target: TransferTarget) -> Option<u32> {
+    match (vid, target) {
+        (Vid::Pros, TransferTarget::Flash) => Some(0x03800000),
+        (Vid::User, TransferTarget::Flash) => Some(0x07800000),
+        _ => None,
+    }
+}
+
 #[repr(u16)]
 #[derive(Copy, Clone, Debug)]
 pub enum Vid {
@@ -98,6 +188,11 @@ pub enum Vid {
     Custom(u8),
 }
 
+impl Vid {
+    /// Every VID with a known purpose.
+    pub const KNOWN: [Vid; 5] = [Self::User, Self::System, Self::Rms, Self::Pros, Self::Mw];
+}
+
 impl Display for Vid {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -146,13 +241,16 @@ impl From<u8> for Vid {
 pub enum FileType {
     Bin,
     Ini,
+    /// Any other extension, such as the `csv` files written by user programs.
+    Other(String),
 }
 
 impl FileType {
-    fn get_name(&self) -> &'static str {
+    fn get_name(&self) -> &str {
         match self {
             Self::Bin => "bin",
             Self::Ini => "ini",
+            Self::Other(name) => name,
         }
     }
 }
@@ -164,6 +262,10 @@ impl TryFrom<&str> for FileType {
         match value.to_lowercase().as_str() {
             "bin" => Ok(Self::Bin),
             "ini" => Ok(Self::Ini),
+            // the type is sent as a null-terminated 4 byte field
+            _ if !value.is_empty() && value.len() < 4 && value.is_ascii() => {
+                Ok(Self::Other(value.to_string()))
+            }
             _ => Err(ParseError::InvalidName(value.to_string())),
         }
     }
@@ -171,9 +273,25 @@ impl TryFrom<&str> for FileType {
 
 pub struct FileTransfer<'a> {
     brain: &'a mut Brain,
+    request: TransferRequest,
     pub parameters: UploadParameters,
 }
 
+/// The arguments of [`Brain::file_transfer_initialize`], kept to start the transfer again.
+struct TransferRequest {
+    direction: TransferDirection,
+    target: TransferTarget,
+    vid: Vid,
+    overwrite: bool,
+    length: u32,
+    address: u32,
+    crc: u32,
+    version: u32,
+    file_type: String,
+    name: String,
+    timestamp: SystemTime,
+}
+
 bitflags! {
     pub struct FileFlags: u8 {
         const _ = !0_u8;
@@ -215,21 +333,70 @@ impl Brain {
         Ok(parse_metadata(packet.send().await?))
     }
 
+    /// Reads the metadata of every file in a directory.
+    /// Each file is a separate round trip, as responses cannot be requested ahead of time.
+    /// The indices can also cover files in other VIDs, so only files in `vid` are returned.
+    pub async fn list_files(
+        &mut self,
+        vid: Vid,
+        flags: FileFlags,
+    ) -> Result<Vec<FileMetadata>, CommunicationError> {
+        let count = self.get_directory_count(vid, flags).await?;
+        let mut files = Vec::with_capacity(count as usize);
+        for i in 0_u8..count as u8 {
+            let meta = self
+                .get_file_metadata_by_index(i, FileFlags::empty())
+                .await?;
+            if u8::from(meta.vid) == u8::from(vid) {
+                files.push(meta);
+            }
+        }
+        Ok(files)
+    }
+
     pub async fn get_file_metadata_by_name(
         &mut self,
         vid: Vid,
         flags: FileFlags,
         filename: &str,
     ) -> Result<FileMetadata, CommunicationError> {
-        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + 24, 0x19);
+        check_name(filename, NAME_LEN)?;
+        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + NAME_LEN, 0x19);
 
         packet.write_u8(vid.into());
         packet.write_u8(flags.bits());
-        packet.write_str(filename, 24);
+        packet.write_str(filename, NAME_LEN);
 
         Ok(parse_metadata(packet.send().await?))
     }
 
+    /// Like [`Brain::get_file_metadata_by_name`], but returns `None` if the file does not exist.
+    pub async fn find_file_metadata(
+        &mut self,
+        vid: Vid,
+        flags: FileFlags,
+        filename: &str,
+    ) -> Result<Option<FileMetadata>, CommunicationError> {
+        match self.get_file_metadata_by_name(vid, flags, filename).await {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(CommunicationError::NegativeAcknowledgement(
+                Nack::ProgramFileError | Nack::NonExistentDirectory,
+            )) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn file_exists(
+        &mut self,
+        vid: Vid,
+        filename: &str,
+    ) -> Result<bool, CommunicationError> {
+        Ok(self
+            .find_file_metadata(vid, FileFlags::empty(), filename)
+            .await?
+            .is_some())
+    }
+
     pub async fn set_file_metadata(
         &mut self,
         vid: Vid,
@@ -240,6 +407,8 @@ impl Brain {
         timestamp: u32,
         version: u32,
     ) -> Result<(), CommunicationError> {
+        check_name(file_type, 4)?;
+        check_name(filename, NAME_LEN)?;
         let mut packet = self.packet(
             size_of::<u8>()
                 + size_of::<u8>()
@@ -247,7 +416,7 @@ impl Brain {
                 + 4
                 + size_of::<u32>()
                 + size_of::<u32>()
-                + 24,
+                + NAME_LEN,
             0x1A,
         );
 
@@ -257,25 +426,27 @@ impl Brain {
         packet.write_str(file_type, 4);
         packet.write_u32(timestamp);
         packet.write_u32(version);
-        packet.write_str(filename, 24);
+        packet.write_str(filename, NAME_LEN);
 
         let _response = packet.send().await?;
         Ok(())
     }
 
-    // send FT complete
+    /// Deletes a file. No transfer is initialized for a delete, so unlike uploads it is not
+    /// followed by a transfer complete packet.
     pub async fn delete_file(
         &mut self,
         vid: Vid,
         flags: DeleteFlags,
         filename: &str,
     ) -> Result<(), CommunicationError> {
+        check_name(filename, NAME_LEN)?;
         debug!("Deleting file {}", filename);
-        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + 24, 0x1B);
+        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + NAME_LEN, 0x1B);
 
         packet.write_u8(vid.into());
         packet.write_u8(flags.bits());
-        packet.write_str(filename, 24);
+        packet.write_str(filename, NAME_LEN);
 
         let _response = packet.send().await?;
         Ok(())
@@ -287,16 +458,109 @@ impl Brain {
         flags: FileFlags,
         filename: &str,
     ) -> Result<u8, CommunicationError> {
-        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + 24, 0x1C);
+        check_name(filename, NAME_LEN)?;
+        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + NAME_LEN, 0x1C);
 
         packet.write_u8(vid.into());
         packet.write_u8(flags.bits());
-        packet.write_str(filename, 24);
+        packet.write_str(filename, NAME_LEN);
 
         let mut response = packet.send().await?;
         Ok(response.read_u8())
     }
 
+    /// Reads the contents of a file from flash. `address` is the load address from its metadata.
+    pub async fn download_file(
+        &mut self,
+        vid: Vid,
+        file_type: FileType,
+        name: &str,
+        address: u32,
+    ) -> Result<Vec<u8>, CommunicationError> {
+        let mut transfer = self
+            .file_transfer_initialize(
+                TransferDirection::Download,
+                TransferTarget::Flash,
+                vid,
+                false,
+                0,
+                address,
+                0,
+                0,
+                file_type,
+                name,
+                SystemTime::now(),
+            )
+            .await?;
+        let size = transfer.parameters.file_size;
+        let chunk_size = transfer.chunk_size() as u32;
+
+        let mut data = Vec::with_capacity(size as usize);
+        for offset in (0..size).step_by(chunk_size as usize) {
+            let len = chunk_size.min(size - offset);
+            // reads must be 4-byte aligned, so the final chunk is rounded up and truncated
+            let chunk = transfer
+                .read(len.next_multiple_of(4) as u16, address + offset)
+                .await?;
+            data.extend_from_slice(&chunk[..len as usize]);
+        }
+        transfer.complete(UploadAction::Nothing).await?;
+        Ok(data)
+    }
+
+    /// Reads a file and checks it against the CRC in its metadata, reading it again if it doesn't
+    /// match. Over bluetooth a read response can be dropped or repeated without the packet CRC
+    /// noticing, leaving a file that is silently wrong. The last read is returned even if it never
+    /// matched, see [`VerifiedDownload::crc_matches`].
+    pub async fn download_file_verified(
+        &mut self,
+        metadata: &FileMetadata,
+    ) -> Result<VerifiedDownload, CommandError> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let file_type = FileType::try_from(metadata.file_type.as_str())?;
+            let data = self
+                .download_file(metadata.vid, file_type, &metadata.name, metadata.addr)
+                .await?;
+            let crc_matches = CRC32.checksum(&data) == metadata.crc;
+            if crc_matches || attempts >= MAX_DOWNLOAD_ATTEMPTS {
+                return Ok(VerifiedDownload {
+                    data,
+                    crc_matches,
+                    attempts,
+                });
+            }
+            debug!("{} does not match its CRC, reading it again", metadata.name);
+        }
+    }
+
+    /// Downloads a file by name, looking up its metadata first.
+    pub async fn read_file(
+        &mut self,
+        vid: Vid,
+        name: &str,
+    ) -> Result<VerifiedDownload, CommandError> {
+        let metadata = self
+            .get_file_metadata_by_name(vid, FileFlags::empty(), name)
+            .await?;
+        self.download_file_verified(&metadata).await
+    }
+
+    /// Closes a transfer that was dropped without being completed, such as after a failed write,
+    /// and releases the connection. Does nothing if no transfer was left open.
+    pub async fn close_abandoned_transfer(&mut self) -> Result<(), CommunicationError> {
+        if !std::mem::take(&mut self.open_transfer) {
+            return Ok(());
+        }
+        debug!("closing abandoned file transfer");
+        let mut packet = self.packet(1, 0x12);
+        packet.write_u8(UploadAction::Nothing.into());
+        let result = packet.send().await;
+        self.unclaim_exclusive().await?;
+        result.map(|_| ())
+    }
+
     pub async fn file_transfer_initialize<'a>(
         &'a mut self,
         direction: TransferDirection,
@@ -311,38 +575,83 @@ impl Brain {
         name: &str,
         timestamp: SystemTime,
     ) -> Result<FileTransfer<'a>, CommunicationError> {
+        check_name(name, NAME_LEN)?;
+        if let Err(err) = self.close_abandoned_transfer().await {
+            debug!("failed to close abandoned transfer: {}", err);
+        }
         self.claim_exclusive().await?;
+        self.open_transfer = true;
+
+        let request = TransferRequest {
+            direction,
+            target,
+            vid,
+            overwrite,
+            length,
+            address,
+            crc,
+            version,
+            file_type: file_type.get_name().to_string(),
+            name: name.to_string(),
+            timestamp,
+        };
+        let parameters = self.send_transfer_initialize(&request).await?;
+        Ok(FileTransfer {
+            brain: self,
+            request,
+            parameters,
+        })
+    }
 
+    async fn send_transfer_initialize(
+        &mut self,
+        request: &TransferRequest,
+    ) -> Result<UploadParameters, CommunicationError> {
         let mut packet = self.packet(
-            size_of::<u8>() * 4 + size_of::<u32>() * 3 + 4 + size_of::<u32>() * 2 + 24,
+            size_of::<u8>() * 4 + size_of::<u32>() * 3 + 4 + size_of::<u32>() * 2 + NAME_LEN,
             0x11,
         );
 
-        packet.write_u8(direction.into());
-        packet.write_u8(target.into());
-        packet.write_u8(vid.into());
-        packet.write_u8(overwrite as u8);
-        packet.write_u32(length);
-        packet.write_u32(address);
-        packet.write_u32(crc);
-        packet.write_str(file_type.get_name(), 4);
-        packet.write_u32(crate::brain::system::convert_to_vex_timestamp(timestamp));
-        packet.write_u32(version);
-        packet.write_str(name, 24);
+        packet.write_u8(request.direction.into());
+        packet.write_u8(request.target.into());
+        packet.write_u8(request.vid.into());
+        packet.write_u8(request.overwrite as u8);
+        packet.write_u32(request.length);
+        packet.write_u32(request.address);
+        packet.write_u32(request.crc);
+        packet.write_str(&request.file_type, 4);
+        packet.write_u32(crate::brain::system::convert_to_vex_timestamp(
+            request.timestamp,
+        ));
+        packet.write_u32(request.version);
+        packet.write_str(&request.name, NAME_LEN);
 
         let mut response: ReceivingBuffer = packet.send().await?;
-        Ok(FileTransfer {
-            brain: self,
-            parameters: UploadParameters {
-                max_packet_size: response.read_u16(),
-                file_size: response.read_u32(),
-                crc: response.read_u32(),
-            },
+        Ok(UploadParameters {
+            max_packet_size: response.read_u16(),
+            file_size: response.read_u32(),
+            crc: response.read_u32(),
         })
     }
 }
 
 impl<'a> FileTransfer<'a> {
+    /// The number of file bytes to send in each [`read`](Self::read) or [`write`](Self::write).
+    pub fn chunk_size(&self) -> u16 {
+        transfer_chunk_size(
+            self.brain.connection.get_max_packet_size(),
+            self.parameters.max_packet_size,
+        )
+    }
+
+    /// Sends the initialization again, for when the brain has lost track of the transfer (it
+    /// answers with [`Nack::UninitializedTransfer`] or [`Nack::InvalidInitialization`]).
+    /// This starts a new transfer, so everything has to be written again, including the link.
+    pub async fn reinitialize(&mut self) -> Result<(), CommunicationError> {
+        self.parameters = self.brain.send_transfer_initialize(&self.request).await?;
+        Ok(())
+    }
+
     pub async fn set_channel(&mut self, channel: Channel) -> Result<(), CommunicationError> {
         let mut packet = self.brain.packet(5, 0x10);
         packet.write_u8(1);
@@ -352,33 +661,27 @@ impl<'a> FileTransfer<'a> {
     }
 
     pub async fn set_link(&mut self, name: &str, vid: Vid) -> Result<(), CommunicationError> {
-        let mut packet = self.brain.packet(1 + 1 + 24, 0x15);
+        check_name(name, NAME_LEN)?;
+        let mut packet = self.brain.packet(1 + 1 + NAME_LEN, 0x15);
 
         packet.write_u8(vid.into());
         packet.write_u8(0);
-        packet.write_str(name, 24);
+        packet.write_str(name, NAME_LEN);
 
         let _response = packet.send().await?;
         Ok(())
     }
 
     pub async fn write(&mut self, slice: &[u8], address: u32) -> Result<(), CommunicationError> {
-        let mut packet = self.brain.packet(
-            size_of::<u32>()
-                + slice.len()
-                + if slice.len() % 4 != 0 {
-                    4 - (slice.len() % 4)
-                } else {
-                    0
-                },
-            0x13,
-        );
+        // the brain only accepts 4-byte aligned writes, so unaligned (final) chunks are zero-padded
+        let padding = (4 - slice.len() % 4) % 4;
+        let mut packet = self
+            .brain
+            .packet(size_of::<u32>() + slice.len() + padding, 0x13);
 
         packet.write_u32(address);
         packet.write_raw(slice);
-        if slice.len() % 4 != 0 {
-            packet.pad(4 - slice.len() % 4);
-        }
+        packet.pad(padding);
         let _response = packet.send().await?;
         Ok(())
     }
@@ -400,6 +703,7 @@ impl<'a> FileTransfer<'a> {
         let mut packet = self.brain.packet(1, 0x12);
         packet.write_u8(upload_action.into());
         let _response = packet.send().await?;
+        self.brain.open_transfer = false;
         self.brain.unclaim_exclusive().await?;
         Ok(())
     }
@@ -414,6 +718,6 @@ fn parse_metadata(mut response: ReceivingBuffer) -> FileMetadata {
         file_type: response.read_str(4),
         timestamp: crate::brain::system::convert_from_vex_timestamp(response.read_u32()),
         version: response.read_u32(),
-        name: response.read_str(24),
+        name: response.read_str(NAME_LEN),
     }
 }
@@ -1,13 +1,66 @@
 use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
 
+use log::debug;
+
+use crate::buffer::ReceivingBuffer;
 use crate::connection::{Packet, RobotConnection};
+use crate::error::CommunicationError;
 
 pub mod competition;
 pub mod filesystem;
 pub mod system;
 
+/// Size of the null-terminated file name field used by most packets.
+pub const NAME_LEN: usize = 24;
+
+/// Checks that a name fits in a fixed-size field, leaving room for the null terminator.
+pub(crate) fn check_name(name: &str, field_len: usize) -> Result<(), CommunicationError> {
+    if name.len() >= field_len {
+        return Err(CommunicationError::NameTooLong(
+            name.to_string(),
+            field_len - 1,
+        ));
+    }
+    Ok(())
+}
+
+/// Traffic counters for a single connection to a brain.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ConnectionStats {
+    /// Packets re-sent after timing out.
+    pub retransmissions: u32,
+    /// Bytes of packets sent, including re-sent packets.
+    pub bytes_sent: u64,
+    /// Packets that received a response.
+    pub round_trips: u32,
+    /// Total time spent waiting for the responses counted in `round_trips`.
+    pub round_trip_time: Duration,
+}
+
 pub struct Brain {
     pub connection: Box<dyn RobotConnection + Send>,
+    pub(crate) stats: ConnectionStats,
+    /// Set while a file transfer is in progress. If it is still set when the next transfer
+    /// starts, the previous one was abandoned (e.g. by an error) and must be closed first.
+    pub(crate) open_transfer: bool,
+}
+
+impl Drop for Brain {
+    fn drop(&mut self) {
+        let stats = self.stats;
+        debug!(
+            "connection closed round_trips={} retransmissions={} bytes_sent={} avg_time={}us",
+            stats.round_trips,
+            stats.retransmissions,
+            stats.bytes_sent,
+            stats
+                .round_trip_time
+                .checked_div(stats.round_trips)
+                .unwrap_or_default()
+                .as_micros()
+        );
+    }
 }
 
 impl Deref for Brain {
@@ -26,10 +79,28 @@ impl DerefMut for Brain {
 
 impl Brain {
     pub fn new(connection: Box<dyn RobotConnection + Send>) -> Self {
-        Self { connection }
+        Self {
+            connection,
+            stats: ConnectionStats::default(),
+            open_transfer: false,
+        }
+    }
+
+    /// Traffic sent over this connection since it was opened.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
     }
 
     fn packet(&mut self, content_len: usize, packet_id: u8) -> Packet {
         Packet::new(packet_id, content_len, self)
     }
+
+    async fn send_simple(&mut self, id: u8) -> Result<ReceivingBuffer, CommunicationError> {
+        self.stats.bytes_sent += 4 /*header*/ + 1 /*id*/ + 2 /*CRC*/;
+        let start = Instant::now();
+        let response = self.connection.send_simple(id).await?;
+        self.stats.round_trips += 1;
+        self.stats.round_trip_time += start.elapsed();
+        Ok(response)
+    }
 }
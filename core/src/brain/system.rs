@@ -1,19 +1,30 @@
 use core::time::Duration;
 use std::fmt::{Display, Formatter};
 use std::mem::size_of;
+use std::num::NonZeroU32;
 use std::ops::Add;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use bitflags::bitflags;
 
-use crate::brain::Brain;
-use crate::brain::filesystem::Vid;
+use crate::brain::{check_name, Brain, NAME_LEN};
+use crate::brain::filesystem::{FileType, TransferDirection, TransferTarget, UploadAction, Vid};
 use crate::error::{CommandError, CommunicationError, ParseError};
 
 const JAN_01_2000: Duration = Duration::from_secs(946684800);
 
+pub const SCREEN_WIDTH: u16 = 480;
+pub const SCREEN_HEIGHT: u16 = 272;
+/// Width of a framebuffer row in pixels, which is wider than the visible screen.
+const SCREEN_STRIDE: u32 = 512;
+
+/// Converts a time to seconds since 2000-01-01. Times outside the representable range are clamped,
+/// as a host clock set before 2000 (e.g. a dead CMOS battery) should not prevent uploads.
 pub fn convert_to_vex_timestamp(timestamp: SystemTime) -> u32 {
-    u32::try_from((timestamp.duration_since(UNIX_EPOCH).unwrap() - JAN_01_2000).as_secs()).unwrap()
+    let since_2000 = timestamp
+        .duration_since(UNIX_EPOCH.add(JAN_01_2000))
+        .unwrap_or(Duration::ZERO);
+    u32::try_from(since_2000.as_secs()).unwrap_or(u32::MAX)
 }
 
 pub fn convert_from_vex_timestamp(timestamp: u32) -> SystemTime {
@@ -30,6 +41,8 @@ pub enum KernelVariable {
 }
 
 impl KernelVariable {
+    pub const ALL: [KernelVariable; 2] = [Self::TeamNumber, Self::RobotName];
+
     pub fn get_max_len(&self) -> usize {
         match self {
             Self::TeamNumber => 7,
@@ -96,22 +109,48 @@ pub struct SystemStatus {
     pub system: Version,
     pub cpu0: Version,
     pub cpu1: Version,
-    pub touch: u8,
+    pub touch: Version,
     pub system_id: u32,
+    /// Only sent by brains with newer firmware.
+    pub details: Option<SystemDetails>,
 }
 
 impl SystemStatus {
-    pub fn new(system: Version, cpu0: Version, cpu1: Version, touch: u8, system_id: u32) -> Self {
+    pub fn new(
+        system: Version,
+        cpu0: Version,
+        cpu1: Version,
+        touch: Version,
+        system_id: u32,
+        details: Option<SystemDetails>,
+    ) -> Self {
         SystemStatus {
             system,
             cpu0,
             cpu1,
             touch,
             system_id,
+            details,
         }
     }
 }
 
+/// The fields following the system id in a system status response.
+pub struct SystemDetails {
+    pub flags: u16,
+    pub eventbrite: u16,
+    pub rom_bootloader_active: bool,
+    pub ram_bootloader_active: bool,
+    pub golden_version: Version,
+}
+
+/// Round-trip latency measured by [`Brain::ping`].
+pub struct Latency {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug)]
 pub enum Channel {
@@ -146,6 +185,12 @@ pub struct SystemVersion {
     product: Product,
 }
 
+impl SystemVersion {
+    pub fn product(&self) -> Product {
+        self.product
+    }
+}
+
 impl Display for SystemVersion {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -183,7 +228,7 @@ bitflags! {
 
 impl Brain {
     pub async fn get_system_version(&mut self) -> Result<SystemVersion, CommandError> {
-        let mut response = self.connection.send_simple(0xA4).await?;
+        let mut response = self.send_simple(0xA4).await?;
 
         Ok(SystemVersion {
             major: response.read_u8(),
@@ -195,8 +240,29 @@ impl Brain {
         })
     }
 
+    /// Measures the round-trip latency of `count` system version requests, the smallest packet
+    /// the brain responds to.
+    pub async fn ping(&mut self, count: NonZeroU32) -> Result<Latency, CommunicationError> {
+        let mut min = Duration::MAX;
+        let mut max = Duration::ZERO;
+        let mut total = Duration::ZERO;
+        for _ in 0..count.get() {
+            let start = Instant::now();
+            self.send_simple(0xA4).await?;
+            let elapsed = start.elapsed();
+            min = min.min(elapsed);
+            max = max.max(elapsed);
+            total += elapsed;
+        }
+        Ok(Latency {
+            min,
+            avg: total / count.get(),
+            max,
+        })
+    }
+
     pub async fn get_product(&mut self) -> Result<String, CommunicationError> {
-        let mut response = self.connection.send_simple(0x21).await?;
+        let mut response = self.send_simple(0x21).await?;
 
         Ok(response.read_str(response.len()))
     }
@@ -207,19 +273,29 @@ impl Brain {
         flags: ExecutionFlags,
         filename: &str,
     ) -> Result<(), CommunicationError> {
-        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + 24, 0x18);
+        check_name(filename, NAME_LEN)?;
+        let mut packet = self.packet(size_of::<u8>() + size_of::<u8>() + NAME_LEN, 0x18);
 
         packet.write_u8(vid.into());
         packet.write_u8(flags.bits());
-        packet.write_str(filename, 24);
+        packet.write_str(filename, NAME_LEN);
 
         let _response = packet.send().await?;
         Ok(())
     }
 
+    /// Stops the program running on the brain, if any.
+    pub async fn stop_program(&mut self) -> Result<(), CommunicationError> {
+        self.execute_program(Vid::User, ExecutionFlags::STOP, "")
+            .await
+    }
+
+    /// The response is laid out as a reserved byte, the system, CPU0 and CPU1 versions,
+    /// the touch version, the system id and, on newer firmware, the [`SystemDetails`].
+    /// It does not include any CPU load information or the competition mode.
     pub async fn get_system_status(&mut self) -> Result<SystemStatus, CommunicationError> {
         let mut response = self.packet(0, 0x22).send().await?;
-        response.skip(1);
+        response.skip(1); // reserved
         let system = Version {
             major: response.read_u8(),
             minor: response.read_u8(),
@@ -238,10 +314,45 @@ impl Brain {
             patch: response.read_u8(),
             extra: response.read_u8(),
         };
-        response.skip(3);
-        let touch = response.read_u8();
+        // unlike the other versions, the touch version is stored in reverse order
+        let touch_extra = response.read_u8();
+        let touch_patch = response.read_u8();
+        let touch_minor = response.read_u8();
+        let touch = Version {
+            major: response.read_u8(),
+            minor: touch_minor,
+            patch: touch_patch,
+            extra: touch_extra,
+        };
         let id = response.read_u32();
-        Ok(SystemStatus::new(system, cpu0, cpu1, touch, id))
+        let details = if response.remaining() >= 12 + size_of::<u16>() {
+            Some(SystemDetails {
+                flags: response.read_u16(),
+                eventbrite: response.read_u16(),
+                rom_bootloader_active: response.read_u16() != 0,
+                ram_bootloader_active: response.read_u16() != 0,
+                golden_version: Version {
+                    major: response.read_u8(),
+                    minor: response.read_u8(),
+                    patch: response.read_u8(),
+                    extra: response.read_u8(),
+                },
+            })
+        } else {
+            None
+        };
+        Ok(SystemStatus::new(system, cpu0, cpu1, touch, id, details))
+    }
+
+    /// The version of the golden (recovery/boot) firmware image.
+    /// There is no dedicated query for it, so it is read from the system status, which only
+    /// includes it on newer firmware; older brains return `None`.
+    pub async fn get_bootloader_version(&mut self) -> Result<Option<Version>, CommunicationError> {
+        Ok(self
+            .get_system_status()
+            .await?
+            .details
+            .map(|details| details.golden_version))
     }
 
     pub async fn send_user_communications(
@@ -288,7 +399,7 @@ impl Brain {
         variable: KernelVariable,
         value: &str,
     ) -> Result<(), CommunicationError> {
-        assert!(value.len() < variable.get_max_len());
+        check_name(value, variable.get_max_len())?;
         let mut packet = self.packet(variable.get_name().len() + 1 + value.len() + 1, 0x2F);
         packet.write_str(variable.get_name(), variable.get_name().len() + 1);
         packet.write_str(value, value.len() + 1);
@@ -296,6 +407,60 @@ impl Brain {
         packet.send().await?;
         Ok(())
     }
-}
 
-// CopyScreenData = 0x28
+    /// Copies the current screen contents into the framebuffer that can be read as a file.
+    pub async fn capture_screen(&mut self) -> Result<(), CommunicationError> {
+        self.packet(0, 0x28).send().await?;
+        Ok(())
+    }
+
+    /// Captures the given region of the screen, returning it as packed 8-bit RGB rows.
+    pub async fn read_screen(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+    ) -> Result<Vec<u8>, CommunicationError> {
+        if x as u32 + width as u32 > SCREEN_WIDTH as u32
+            || y as u32 + height as u32 > SCREEN_HEIGHT as u32
+        {
+            return Err(CommunicationError::InvalidScreenRegion(x, y, width, height));
+        }
+        self.capture_screen().await?;
+
+        let mut transfer = self
+            .file_transfer_initialize(
+                TransferDirection::Download,
+                TransferTarget::Screen,
+                Vid::System,
+                false,
+                SCREEN_STRIDE * SCREEN_HEIGHT as u32 * 4,
+                0,
+                0,
+                0,
+                FileType::Bin,
+                "screen",
+                SystemTime::now(),
+            )
+            .await?;
+        let chunk_size = transfer.chunk_size() as u32;
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 3);
+        for row in y as u32..(y + height) as u32 {
+            let start = (row * SCREEN_STRIDE + x as u32) * 4;
+            let end = start + width as u32 * 4;
+            for address in (start..end).step_by(chunk_size as usize) {
+                let data = transfer
+                    .read(chunk_size.min(end - address) as u16, address)
+                    .await?;
+                // pixels are stored as little-endian 0x00RRGGBB
+                for pixel in data.chunks_exact(4) {
+                    pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+                }
+            }
+        }
+        transfer.complete(UploadAction::Nothing).await?;
+        Ok(pixels)
+    }
+}
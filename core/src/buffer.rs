@@ -8,7 +8,7 @@ pub struct ReceivingBuffer {
 }
 
 impl ReceivingBuffer {
-    pub(crate) fn new(buffer: Box<[u8]>, pos: usize) -> ReceivingBuffer {
+    pub fn new(buffer: Box<[u8]>, pos: usize) -> ReceivingBuffer {
         ReceivingBuffer { buffer, pos }
     }
 }
@@ -165,6 +165,11 @@ impl ReceivingBuffer {
         self.pos += amount;
     }
 
+    /// Number of bytes after the current position, including the trailing CRC of the packet.
+    pub fn remaining(&self) -> usize {
+        self.buffer.len().saturating_sub(self.pos)
+    }
+
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
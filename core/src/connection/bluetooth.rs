@@ -1,6 +1,6 @@
 use std::convert::TryInto;
+use std::ops::RangeInclusive;
 use std::io::Write;
-use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
@@ -15,7 +15,7 @@ use tokio::sync::mpsc::Receiver;
 use uuid::Uuid;
 
 use crate::buffer::ReceivingBuffer;
-use crate::connection::{CRC16, Nack, RESPONSE_HEADER, RobotConnection};
+use crate::connection::{CRC16, Nack, RESPONSE_HEADER, RobotConnection, Timeouts};
 use crate::error::{CommunicationError, ConnectionError};
 
 const V5_ROBOT_SERVICE: Uuid = Uuid::from_u128(0x08590f7e_db05_467e_8757_72f6faeb13d5);
@@ -32,6 +32,20 @@ const CHARACTERISTIC_CODE: Uuid = Uuid::from_u128(0x08590f7e_db05_467e_8757_72f6
 // READ | WRITE_WITHOUT_RESPONSE | WRITE
 const AUTH_REQUIRED: u32 = 0xdeadface;
 
+/// Each write carries a 3 byte ATT header, so packets can be at most the MTU minus this.
+const ATT_HEADER_LEN: u16 = 3;
+/// The ATT MTU every brain supports, which leaves room for 244 byte packets.
+pub const DEFAULT_ATT_MTU: u16 = 247;
+/// The range of MTUs allowed by the ATT protocol.
+pub const ATT_MTU_RANGE: RangeInclusive<u16> = 23..=517;
+
+/// The largest packet that fits in a single write with the given ATT MTU, or with the
+/// default MTU if it is unknown.
+pub fn max_packet_size_for_mtu(mtu: Option<u16>) -> u16 {
+    mtu.unwrap_or(DEFAULT_ATT_MTU)
+        .saturating_sub(ATT_HEADER_LEN)
+}
+
 pub(crate) struct Characteristics {
     pub(crate) tx_data: Characteristic,
     pub(crate) rx_data: Characteristic,
@@ -41,10 +55,13 @@ pub(crate) struct Characteristics {
 
 pub(crate) async fn connect_to_robot(
     mac_address: Option<String>,
-    mut pin: Option<String>,
+    pin: Option<String>,
+    scan_timeout: Duration,
 ) -> Result<(btleplug::platform::Peripheral, Characteristics), ConnectionError> {
-    let mac_address =
-        mac_address.map(|address| BDAddr::from_str(&address).expect("Invalid MAC address"));
+    // a PIN given up front is checked before connecting, so scripts fail fast instead of
+    // finding out after the (slow) device search
+    let pin = pin.as_deref().map(parse_pin).transpose()?;
+    let mac_address = mac_address.as_deref().map(parse_mac_address).transpose()?;
 
     let manager = match btleplug::platform::Manager::new().await {
         Ok(man) => man,
@@ -64,23 +81,29 @@ pub(crate) async fn connect_to_robot(
 
     let mut device: Option<btleplug::platform::Peripheral> = None;
 
-    while let Some(event) = events.next().await {
-        match event {
-            CentralEvent::DeviceDiscovered(id) => {
-                if let Some(peripheral) = find_vex_device(mac_address, adapter, &id).await {
-                    device = Some(peripheral);
+    // with many devices nearby the event stream never ends, so give up after a while
+    let search = async {
+        while let Some(event) = events.next().await {
+            match event {
+                CentralEvent::DeviceDiscovered(id) => {
+                    if let Some(peripheral) = find_vex_device(mac_address, adapter, &id).await {
+                        device = Some(peripheral);
+                    }
                 }
-            }
-            CentralEvent::DeviceUpdated(id) => {
-                if let Some(peripheral) = find_vex_device(mac_address, adapter, &id).await {
-                    device = Some(peripheral);
+                CentralEvent::DeviceUpdated(id) => {
+                    if let Some(peripheral) = find_vex_device(mac_address, adapter, &id).await {
+                        device = Some(peripheral);
+                    }
                 }
+                _ => {}
+            }
+            if device.is_some() {
+                break;
             }
-            _ => {}
-        }
-        if device.is_some() {
-            break;
         }
+    };
+    if tokio::time::timeout(scan_timeout, search).await.is_err() {
+        debug!("no brain found within {:?}", scan_timeout);
     }
     debug!(
         "device search took {}ms",
@@ -130,26 +153,20 @@ pub(crate) async fn connect_to_robot(
 
     let vec = peripheral.read(&code).await?;
     if u32::from_be_bytes(vec[0..4].try_into().unwrap()) == AUTH_REQUIRED {
-        if pin.is_none() {
-            debug!("Sending PIN display request.");
-            peripheral
-                .write(&code, &[0xFF, 0xFF, 0xFF, 0xFF], WriteType::WithoutResponse)
-                .await?;
-
-            println!("Please enter the PIN shown on the V5 brain");
-            let mut str = String::new();
-            std::io::stdin()
-                .read_line(&mut str)
-                .expect("Failed to read stdin");
-            pin = Some(str);
-        }
+        let pin = match pin {
+            Some(pin) => pin,
+            None => {
+                debug!("Sending PIN display request.");
+                peripheral
+                    .write(&code, &[0xFF, 0xFF, 0xFF, 0xFF], WriteType::WithoutResponse)
+                    .await?;
 
-        assert!(pin.is_some());
-        let pin = pin.as_deref().map_or(Err(()), parse_pin);
-        if pin.is_err() {
-            return Err(ConnectionError::InvalidPIN);
-        }
-        let pin = pin.unwrap();
+                println!("Please enter the PIN shown on the V5 brain");
+                let mut str = String::new();
+                std::io::stdin().read_line(&mut str)?;
+                parse_pin(&str)?
+            }
+        };
 
         peripheral
             .write(&code, &pin, WriteType::WithoutResponse)
@@ -172,7 +189,10 @@ pub(crate) async fn connect_to_robot(
     ))
 }
 
-pub(crate) async fn find_packet_header(port: &mut Receiver<u8>) -> Result<(), CommunicationError> {
+pub(crate) async fn find_packet_header(
+    port: &mut Receiver<u8>,
+    timeout: Duration,
+) -> Result<(), CommunicationError> {
     let mut value = 0;
     let mut i = 0;
     let start = SystemTime::now();
@@ -187,19 +207,13 @@ pub(crate) async fn find_packet_header(port: &mut Receiver<u8>) -> Result<(), Co
             continue;
         }
 
-        match port.recv().await {
-            Some(v) => value = v,
-            None => {
-                tokio::time::sleep(Duration::from_millis(2)).await;
-                value = 0;
-                if SystemTime::now()
-                    .duration_since(start)
-                    .unwrap_or(Duration::ZERO)
-                    > Duration::from_millis(1000)
-                {
-                    return Err(CommunicationError::TimedOut);
-                }
-            }
+        let elapsed = SystemTime::now()
+            .duration_since(start)
+            .unwrap_or(Duration::ZERO);
+        match tokio::time::timeout(timeout.saturating_sub(elapsed), port.recv()).await {
+            Ok(Some(v)) => value = v,
+            Ok(None) => return Err(CommunicationError::Eof),
+            Err(_) => return Err(CommunicationError::TimedOut),
         }
     }
     debug!(
@@ -215,6 +229,8 @@ pub(crate) struct BluetoothConnection {
     user_tx: Characteristic,
     user_rx: Receiver<u8>,
     peripheral: btleplug::platform::Peripheral,
+    max_packet_size: u16,
+    timeouts: Timeouts,
 }
 
 impl BluetoothConnection {
@@ -224,6 +240,8 @@ impl BluetoothConnection {
         user_tx: Characteristic,
         user_rx: Characteristic,
         peripheral: btleplug::platform::Peripheral,
+        mtu: Option<u16>,
+        timeouts: Timeouts,
     ) -> BluetoothConnection {
         let (system_send, system_buf) = tokio::sync::mpsc::channel(1024);
         let (user_send, user_buf) = tokio::sync::mpsc::channel(1024);
@@ -263,12 +281,19 @@ impl BluetoothConnection {
             });
         }
 
+        // btleplug does not report the MTU it negotiated, so unless one is given the default is
+        // assumed
+        let max_packet_size = max_packet_size_for_mtu(mtu);
+        debug!("using {} byte bluetooth packets", max_packet_size);
+
         BluetoothConnection {
             system_tx,
             system_rx: system_buf,
             user_tx,
             user_rx: user_buf,
             peripheral,
+            max_packet_size,
+            timeouts,
         }
     }
 }
@@ -276,7 +301,7 @@ impl BluetoothConnection {
 #[async_trait]
 impl RobotConnection for BluetoothConnection {
     fn get_max_packet_size(&self) -> u16 {
-        244
+        self.max_packet_size
     }
 
     async fn send_packet(&mut self, data: &[u8]) -> Result<ReceivingBuffer, CommunicationError> {
@@ -284,7 +309,7 @@ impl RobotConnection for BluetoothConnection {
             .write(&self.system_tx, data, WriteType::WithoutResponse)
             .await?;
 
-        find_packet_header(&mut self.system_rx).await?;
+        find_packet_header(&mut self.system_rx, self.timeouts.for_packet(data)).await?;
 
         let mut payload = Vec::with_capacity(64);
         payload.extend_from_slice(&RESPONSE_HEADER);
@@ -355,19 +380,34 @@ impl RobotConnection for BluetoothConnection {
     }
 }
 
-fn parse_pin(str: &str) -> Result<[u8; 4], ()> {
-    let mut chars = str.chars();
-    let mut output = [0_u8; 4];
-    for i in 0..4 {
-        if let Some(next) = chars.next() {
-            if next.is_digit(10) {
-                output[i] = next.to_digit(10).unwrap() as u8;
-                continue;
-            }
-        }
-        return Err(());
+/// Parses the 4 digit PIN shown on the brain's screen.
+pub fn parse_pin(str: &str) -> Result<[u8; 4], ConnectionError> {
+    let digits: Vec<u8> = str
+        .trim()
+        .chars()
+        .map(|char| char.to_digit(10).map(|digit| digit as u8))
+        .collect::<Option<_>>()
+        .ok_or(ConnectionError::InvalidPIN)?;
+    digits.try_into().map_err(|_| ConnectionError::InvalidPIN)
+}
+
+/// Parses a MAC address written with colons, dashes, dots or no separators at all, in any case
+/// (e.g. `AA:BB:CC:DD:EE:FF`, `aa-bb-cc-dd-ee-ff` or `aabbccddeeff`).
+pub fn parse_mac_address(str: &str) -> Result<BDAddr, ConnectionError> {
+    let invalid = || ConnectionError::InvalidMacAddress(str.to_string());
+    let hex: String = str
+        .trim()
+        .chars()
+        .filter(|char| !matches!(char, ':' | '-' | '.' | ' '))
+        .collect();
+    if hex.len() != 12 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut address = [0_u8; 6];
+    for (i, byte) in address.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
     }
-    Ok(output)
+    Ok(BDAddr::from(address))
 }
 
 async fn find_vex_device(
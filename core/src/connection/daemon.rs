@@ -1,5 +1,8 @@
+use std::collections::VecDeque;
+use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+use log::debug;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
@@ -7,6 +10,14 @@ use crate::buffer::ReceivingBuffer;
 use crate::connection::RobotConnection;
 use crate::error::{CommunicationError, ConnectionError};
 
+/// Sent by the daemon in place of the max packet size when it cannot accept another client.
+pub const DAEMON_FULL: u16 = 0;
+
+/// Commands sent between the daemon and its clients. Each is followed by a big-endian `u16`
+/// length and that many bytes, except for the exclusive claim and reset commands which clients
+/// send on their own. The daemon answers `SendSystem` with a `SendSystem` frame holding the
+/// response, and forwards user serial output to every client as `SendUser` frames, so the two
+/// can be told apart on the client.
 #[repr(u8)]
 pub enum DaemonCommand {
     SendSystem = 0,
@@ -14,6 +25,9 @@ pub enum DaemonCommand {
     ClaimExclusive = 2,
     UnclaimExclusive = 3,
     Reset = 4,
+    /// Asks the daemon for its other clients. It answers with a `ListClients` frame holding one
+    /// line of text per client.
+    ListClients = 5,
 }
 
 impl From<DaemonCommand> for u8 {
@@ -32,6 +46,7 @@ impl TryFrom<u8> for DaemonCommand {
             2 => Ok(DaemonCommand::ClaimExclusive),
             3 => Ok(DaemonCommand::UnclaimExclusive),
             4 => Ok(DaemonCommand::Reset),
+            5 => Ok(DaemonCommand::ListClients),
             _ => Err(()),
         }
     }
@@ -40,6 +55,46 @@ impl TryFrom<u8> for DaemonCommand {
 pub struct SharedConnection {
     stream: TcpStream,
     max_packet_size: u16,
+    /// Whether this client currently holds the daemon's exclusive claim.
+    exclusive: bool,
+    /// Bytes received from the daemon that do not yet form a complete frame.
+    incoming: Vec<u8>,
+    /// User serial output received while waiting for a system response.
+    user_data: VecDeque<u8>,
+}
+
+impl SharedConnection {
+    /// Reads the next frame sent by the daemon. This is cancel safe, as partially received frames
+    /// are kept until the rest arrives.
+    async fn read_frame(&mut self) -> Result<(DaemonCommand, Vec<u8>), CommunicationError> {
+        loop {
+            if self.incoming.len() >= 3 {
+                let len = u16::from_be_bytes([self.incoming[1], self.incoming[2]]) as usize;
+                if self.incoming.len() >= 3 + len {
+                    let frame: Vec<u8> = self.incoming.drain(..3 + len).collect();
+                    let command = DaemonCommand::try_from(frame[0]).map_err(|_| {
+                        std::io::Error::new(ErrorKind::InvalidData, "unknown daemon command")
+                    })?;
+                    return Ok((command, frame[3..].to_vec()));
+                }
+            }
+            if self.stream.read_buf(&mut self.incoming).await? == 0 {
+                return Err(CommunicationError::Eof);
+            }
+        }
+    }
+}
+
+impl Drop for SharedConnection {
+    fn drop(&mut self) {
+        // a client that exits mid-transfer would otherwise leave the daemon locked.
+        // drop cannot await, so this is a best-effort non-blocking write.
+        if self.exclusive {
+            let _ = self
+                .stream
+                .try_write(&[DaemonCommand::UnclaimExclusive.into()]);
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -52,6 +107,7 @@ impl RobotConnection for SharedConnection {
         self.stream
             .write_u8(DaemonCommand::ClaimExclusive.into())
             .await?;
+        self.exclusive = true;
         Ok(())
     }
 
@@ -59,6 +115,7 @@ impl RobotConnection for SharedConnection {
         self.stream
             .write_u8(DaemonCommand::UnclaimExclusive.into())
             .await?;
+        self.exclusive = false;
         Ok(())
     }
 
@@ -68,11 +125,15 @@ impl RobotConnection for SharedConnection {
             .await?;
         self.stream.write_u16(data.len() as u16).await?;
         self.stream.write_all(data).await?;
-        let len = self.stream.read_u16().await?;
-        let mut vec1 = vec![0_u8; len as usize];
-        vec1.resize(len as usize, 0_u8);
-        self.stream.read_exact(&mut vec1).await?;
-        return Ok(ReceivingBuffer::new(vec1.into_boxed_slice(), 4 + 2));
+        loop {
+            match self.read_frame().await? {
+                (DaemonCommand::SendSystem, response) => {
+                    return Ok(ReceivingBuffer::new(response.into_boxed_slice(), 4 + 2));
+                }
+                (DaemonCommand::SendUser, output) => self.user_data.extend(output),
+                _ => debug!("ignoring unexpected daemon frame"),
+            }
+        }
     }
 
     async fn write_serial(&mut self, data: &[u8]) -> Result<usize, CommunicationError> {
@@ -83,7 +144,17 @@ impl RobotConnection for SharedConnection {
     }
 
     async fn read_serial(&mut self, data: &mut [u8]) -> Result<usize, CommunicationError> {
-        Ok(self.stream.read(data).await?)
+        while self.user_data.is_empty() {
+            match self.read_frame().await? {
+                (DaemonCommand::SendUser, output) => self.user_data.extend(output),
+                _ => debug!("ignoring unexpected daemon frame"),
+            }
+        }
+        let len = data.len().min(self.user_data.len());
+        for (byte, output) in data.iter_mut().zip(self.user_data.drain(..len)) {
+            *byte = output;
+        }
+        Ok(len)
     }
 
     async fn reset(&mut self) -> Result<(), CommunicationError> {
@@ -101,9 +172,15 @@ pub(crate) async fn open_connection(port: u16) -> Result<SharedConnection, Conne
     let mut stream =
         TcpStream::connect(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)).await?;
     let max_packet_size = stream.read_u16().await?;
+    if max_packet_size == DAEMON_FULL {
+        return Err(ConnectionError::DaemonFull);
+    }
 
     Ok(SharedConnection {
         stream,
         max_packet_size,
+        exclusive: false,
+        incoming: Vec::new(),
+        user_data: VecDeque::new(),
     })
 }
@@ -1,7 +1,9 @@
 use std::fmt::{Display, Formatter};
 use std::mem::size_of;
+use std::time::{Duration, Instant};
 
 use crc::{Crc, CRC_16_XMODEM};
+use log::{debug, warn};
 
 use crate::brain::Brain;
 use crate::buffer::ReceivingBuffer;
@@ -12,8 +14,15 @@ mod bluetooth;
 pub mod daemon;
 mod serial;
 
+pub use bluetooth::{
+    max_packet_size_for_mtu, parse_mac_address, parse_pin, ATT_MTU_RANGE, DEFAULT_ATT_MTU,
+};
+
 pub(crate) const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
 
+/// How many times a packet is re-sent after timing out before giving up.
+pub(crate) const MAX_RETRIES: u32 = 3;
+
 pub(crate) const PACKET_HEADER: [u8; 4] = [0xc9, 0x36, 0xb8, 0x47];
 pub(crate) const RESPONSE_HEADER: [u8; 2] = [0xAA, 0x55];
 
@@ -66,14 +75,60 @@ impl Display for Nack {
     }
 }
 
+/// How long to wait for the brain to respond to a packet.
+#[derive(Copy, Clone, Debug)]
+pub struct Timeouts {
+    /// Queries and commands, such as reading the system version.
+    pub query: Duration,
+    /// File transfer packets, which can take much longer as the brain writes to flash.
+    pub transfer: Duration,
+    /// Finding and opening the connection, such as searching for the brain over bluetooth.
+    pub connect: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            query: Duration::from_millis(1000),
+            transfer: Duration::from_millis(2000),
+            connect: Duration::from_millis(10000),
+        }
+    }
+}
+
+impl Timeouts {
+    pub(crate) fn for_packet(&self, data: &[u8]) -> Duration {
+        // extended packets are laid out as header, 0x56, command id
+        match (data.get(4), data.get(5)) {
+            (Some(0x56), Some(0x11..=0x15)) => self.transfer,
+            _ => self.query,
+        }
+    }
+}
+
+/// Describes how to reach a brain.
+///
+/// The options own everything needed to connect, so a stored copy can be passed to
+/// [`connect_to_brain`] again to re-establish a dropped connection.
+#[derive(Clone)]
 pub enum RobotConnectionOptions {
     Serial {
         port: Option<String>,
+        baud_rate: u32,
+        /// Connect through a tethered controller rather than directly to the brain.
+        controller: bool,
+        /// Open the brain's user port for user serial. Without it, only the system port is used.
+        user_port: bool,
+        auto_reconnect: bool,
+        timeouts: Timeouts,
     },
 
     Bluetooth {
         mac_address: Option<String>,
         pin: Option<String>,
+        /// Overrides the ATT MTU used to size packets, see [`ATT_MTU_RANGE`].
+        mtu: Option<u16>,
+        timeouts: Timeouts,
     },
     Daemon {
         port: u16,
@@ -84,27 +139,44 @@ pub async fn connect_to_brain(
     options: RobotConnectionOptions,
 ) -> Result<Brain, crate::error::ConnectionError> {
     match options {
-        RobotConnectionOptions::Serial { port } => {
-            let (system, user) = serial::find_ports(port)?;
-            Ok(Brain::new(Box::new(
-                serial::open_connection(system, user).await?,
-            )))
-        }
-        RobotConnectionOptions::Bluetooth { mac_address, pin } => {
-            match bluetooth::connect_to_robot(mac_address, pin).await {
-                Ok((peripheral, characteristics)) => Ok(Brain::new(Box::new(
-                    BluetoothConnection::create(
-                        characteristics.rx_data,
-                        characteristics.tx_data,
-                        characteristics.rx_user,
-                        characteristics.tx_user,
-                        peripheral,
-                    )
-                    .await,
-                ))),
-                Err(err) => Err(err),
-            }
-        }
+        RobotConnectionOptions::Serial {
+            port,
+            baud_rate,
+            controller,
+            user_port,
+            auto_reconnect,
+            timeouts,
+        } => Ok(Brain::new(Box::new(
+            serial::open_connection(
+                port,
+                baud_rate,
+                controller,
+                user_port,
+                auto_reconnect,
+                timeouts,
+            )
+            .await?,
+        ))),
+        RobotConnectionOptions::Bluetooth {
+            mac_address,
+            pin,
+            mtu,
+            timeouts,
+        } => match bluetooth::connect_to_robot(mac_address, pin, timeouts.connect).await {
+            Ok((peripheral, characteristics)) => Ok(Brain::new(Box::new(
+                BluetoothConnection::create(
+                    characteristics.rx_data,
+                    characteristics.tx_data,
+                    characteristics.rx_user,
+                    characteristics.tx_user,
+                    peripheral,
+                    mtu,
+                    timeouts,
+                )
+                .await,
+            ))),
+            Err(err) => Err(err),
+        },
         RobotConnectionOptions::Daemon { port } => {
             Ok(Brain::new(Box::new(daemon::open_connection(port).await?)))
         }
@@ -150,7 +222,37 @@ impl<'a> Packet<'a> {
         assert_eq!(self.buffer.len() - size_of::<u16>(), self.pos);
 
         self.write_raw(&CRC16.checksum(&self.buffer[..self.pos]).to_be_bytes());
-        self.brain.connection.send_packet(&self.buffer).await
+        let mut attempts = 0;
+        loop {
+            self.brain.stats.bytes_sent += self.buffer.len() as u64;
+            let start = Instant::now();
+            let result = self.brain.connection.send_packet(&self.buffer).await;
+            let elapsed = start.elapsed();
+            debug!(
+                "packet id={:#04x} size={} attempt={} time={}us ok={}",
+                self.packet_id,
+                self.buffer.len(),
+                attempts + 1,
+                elapsed.as_micros(),
+                result.is_ok()
+            );
+            match result {
+                Ok(response) => {
+                    self.brain.stats.round_trips += 1;
+                    self.brain.stats.round_trip_time += elapsed;
+                    return Ok(response);
+                }
+                Err(CommunicationError::TimedOut) if attempts < MAX_RETRIES => {
+                    attempts += 1;
+                    self.brain.stats.retransmissions += 1;
+                    warn!(
+                        "packet {:#04x} timed out, retrying ({}/{})",
+                        self.packet_id, attempts, MAX_RETRIES
+                    );
+                }
+                result => return result,
+            }
+        }
     }
 }
 
@@ -1,23 +1,42 @@
 use std::io::ErrorKind::WouldBlock;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
-use log::debug;
+use log::{debug, info, warn};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio_serial::{
     DataBits, FlowControl, Parity, SerialPort, SerialPortBuilderExt, SerialPortType, SerialStream,
+    UsbPortInfo,
 };
 
 use crate::buffer::ReceivingBuffer;
-use crate::connection::{CRC16, Nack, RESPONSE_HEADER, RobotConnection};
+use crate::connection::{CRC16, Nack, RESPONSE_HEADER, RobotConnection, Timeouts};
 use crate::error::{CommunicationError, ConnectionError};
 
+/// How long to wait between attempts to find and open the brain's ports.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// How long a single read or write on an open port may take.
+const PORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const VEX_VID: u16 = 0x2888;
+/// The brain, which exposes a system and a user port.
+const BRAIN_PID: u16 = 0x0501;
+/// A controller tethered over USB, which exposes a single port.
+const CONTROLLER_PID: u16 = 0x0503;
+
 pub struct SerialPortConnection {
     system_port: SerialStream,
     communications_port: Option<SerialStream>,
+    port: Option<String>,
+    baud_rate: u32,
+    controller: bool,
+    user_port: bool,
+    auto_reconnect: bool,
+    timeouts: Timeouts,
 }
 
 pub(crate) async fn find_packet_header<T: AsyncRead + AsyncReadExt + Unpin>(
     port: &mut T,
+    timeout: Duration,
 ) -> Result<(), CommunicationError> {
     let mut value = 0;
     let mut i = 0;
@@ -41,7 +60,7 @@ pub(crate) async fn find_packet_header<T: AsyncRead + AsyncReadExt + Unpin>(
                 if SystemTime::now()
                     .duration_since(start)
                     .unwrap_or(Duration::ZERO)
-                    > Duration::from_millis(1000)
+                    > timeout
                 {
                     return Err(CommunicationError::TimedOut);
                 }
@@ -56,16 +75,39 @@ pub(crate) async fn find_packet_header<T: AsyncRead + AsyncReadExt + Unpin>(
     Ok(())
 }
 
-#[async_trait::async_trait]
-impl RobotConnection for SerialPortConnection {
-    fn get_max_packet_size(&self) -> u16 {
-        0b0111_1111_1111_1111
+impl SerialPortConnection {
+    /// Re-discovers and re-opens the brain's ports after the connection drops (e.g. a loose cable).
+    async fn reconnect(&mut self) -> Result<(), CommunicationError> {
+        tokio::time::sleep(RECONNECT_DELAY).await;
+        match find_and_open_ports(
+            self.port.clone(),
+            self.controller,
+            self.user_port,
+            self.baud_rate,
+            self.timeouts.connect,
+        )
+        .await
+        {
+            Ok((system_port, user_port)) => {
+                info!("reconnected to brain");
+                self.system_port = system_port;
+                self.communications_port = user_port;
+                Ok(())
+            }
+            Err(err) => {
+                debug!("failed to reconnect: {}", err);
+                Err(CommunicationError::Eof)
+            }
+        }
     }
 
-    async fn send_packet(&mut self, data: &[u8]) -> Result<ReceivingBuffer, CommunicationError> {
-        self.system_port.write_all(&data).await?;
+    async fn try_send_packet(
+        &mut self,
+        data: &[u8],
+    ) -> Result<ReceivingBuffer, CommunicationError> {
+        self.system_port.write_all(data).await?;
 
-        find_packet_header(&mut self.system_port).await?;
+        find_packet_header(&mut self.system_port, self.timeouts.for_packet(data)).await?;
 
         let mut payload = Vec::with_capacity(64);
         payload.extend_from_slice(&RESPONSE_HEADER);
@@ -99,25 +141,58 @@ impl RobotConnection for SerialPortConnection {
 
         Ok(ReceivingBuffer::new(payload.into_boxed_slice(), start + 2))
     }
+}
+
+#[async_trait::async_trait]
+impl RobotConnection for SerialPortConnection {
+    fn get_max_packet_size(&self) -> u16 {
+        0b0111_1111_1111_1111
+    }
+
+    async fn send_packet(&mut self, data: &[u8]) -> Result<ReceivingBuffer, CommunicationError> {
+        match self.try_send_packet(data).await {
+            Err(CommunicationError::IoError(err)) if self.auto_reconnect => {
+                warn!("lost connection to brain ({}), reconnecting", err);
+                self.reconnect().await?;
+                self.try_send_packet(data).await
+            }
+            result => result,
+        }
+    }
 
     async fn write_serial(&mut self, data: &[u8]) -> Result<usize, CommunicationError> {
         if let Some(port) = self.communications_port.as_mut() {
-            Ok(port.write(data).await?)
+            match port.write(data).await {
+                Err(err) if self.auto_reconnect => {
+                    warn!("lost connection to brain ({}), reconnecting", err);
+                    self.reconnect().await?;
+                    Ok(0)
+                }
+                result => Ok(result?),
+            }
         } else {
-            todo!()
+            Err(CommunicationError::UserSerialUnavailable)
         }
     }
 
     async fn read_serial(&mut self, data: &mut [u8]) -> Result<usize, CommunicationError> {
         if let Some(port) = self.communications_port.as_mut() {
-            Ok(port.read(data).await?)
+            match port.read(data).await {
+                Err(err) if self.auto_reconnect => {
+                    warn!("lost connection to brain ({}), reconnecting", err);
+                    self.reconnect().await?;
+                    Ok(0)
+                }
+                result => Ok(result?),
+            }
         } else {
-            todo!()
+            Err(CommunicationError::UserSerialUnavailable)
         }
     }
 
+    /// Serial has no way to reset the link itself, so the ports are found and opened again.
     async fn reset(&mut self) -> Result<(), CommunicationError> {
-        todo!()
+        self.reconnect().await
     }
 
     async fn shutdown(&mut self) -> Result<(), CommunicationError> {
@@ -129,10 +204,16 @@ impl RobotConnection for SerialPortConnection {
     }
 }
 
-pub(crate) fn find_ports(_port: Option<String>) -> Result<(String, String), ConnectionError> {
+/// Returns the system port and, unless connecting through a controller or `user_port` is false,
+/// the user port if the brain exposes one.
+pub(crate) fn find_ports(
+    _port: Option<String>,
+    controller: bool,
+    user_port: bool,
+) -> Result<(String, Option<String>), ConnectionError> {
     let mut system = Vec::new();
     let mut user = Vec::new();
-    let mut controller = Vec::new();
+    let mut controllers = Vec::new();
 
     let mut unknown = Vec::new();
 
@@ -141,61 +222,160 @@ pub(crate) fn find_ports(_port: Option<String>) -> Result<(String, String), Conn
         Ok(ports) => {
             for port in ports {
                 if let SerialPortType::UsbPort(info) = &port.port_type {
-                    if info.pid == 0x0501 && info.vid == 0x2888 {
-                        if let Some(product) = &info.product {
-                            let product = product.to_lowercase();
-                            if product.contains("user") {
-                                &mut user
-                            } else if product.contains("system")
-                                || product.contains("communications")
-                            {
-                                &mut system
-                            } else if product.contains("controller") {
-                                &mut controller
-                            } else {
-                                &mut unknown
+                    if info.vid != VEX_VID {
+                        continue;
+                    }
+                    if info.pid == CONTROLLER_PID {
+                        controllers.push(port.port_name.clone());
+                    } else if info.pid == BRAIN_PID {
+                        let product = info.product.as_deref().unwrap_or("").to_lowercase();
+                        if product.contains("user") {
+                            &mut user
+                        } else if product.contains("system") || product.contains("communications") {
+                            &mut system
+                        } else {
+                            match interface_number(info) {
+                                Some(0) => &mut system,
+                                Some(2) => &mut user,
+                                _ => &mut unknown,
                             }
-                            .push(port.port_name.clone())
                         }
+                        .push(port.port_name.clone())
                     }
                 }
             }
 
-            if system.is_empty() || user.is_empty() {
-                if unknown.len() >= 2 {
-                    return Ok((unknown[0].clone(), unknown[1].clone()));
-                }
-                return Err(ConnectionError::DeviceNotFound);
+            if controller {
+                // a tethered controller only exposes one port, which forwards packets over radio
+                return match controllers.first() {
+                    Some(port) => Ok((port.clone(), None)),
+                    None => Err(ConnectionError::DeviceNotFound),
+                };
             }
 
-            Ok((system[0].clone(), user[0].clone()))
+            if system.is_empty() && unknown.len() >= 2 {
+                let user = user_port.then(|| unknown[1].clone());
+                return Ok((unknown[0].clone(), user));
+            }
+            let Some(system) = system.first().or(unknown.first()) else {
+                return Err(ConnectionError::DeviceNotFound);
+            };
+            if !user_port {
+                return Ok((system.clone(), None));
+            }
+            match user.first() {
+                Some(user) => Ok((system.clone(), Some(user.clone()))),
+                None => {
+                    // some adapters only expose the system port, which is enough for everything
+                    // but user serial
+                    warn!("brain has no user port, user serial will not be available");
+                    Ok((system.clone(), None))
+                }
+            }
         }
         Err(err) => Err(ConnectionError::SerialPortError(err)),
     }
 }
 
+/// Some Windows drivers report an empty product string, so the USB interface is used instead.
+/// The brain exposes the system port on interface 0 and the user port on interface 2.
+#[cfg(windows)]
+fn interface_number(info: &UsbPortInfo) -> Option<u8> {
+    info.interface
+}
+
+#[cfg(not(windows))]
+fn interface_number(_info: &UsbPortInfo) -> Option<u8> {
+    None
+}
+
 pub(crate) async fn open_connection(
-    system: String,
-    user: String,
+    port: Option<String>,
+    baud_rate: u32,
+    controller: bool,
+    user_port: bool,
+    auto_reconnect: bool,
+    timeouts: Timeouts,
 ) -> Result<SerialPortConnection, ConnectionError> {
-    let system_port = tokio_serial::new(system, 115200)
-        .parity(Parity::None)
-        .data_bits(DataBits::Eight)
-        .timeout(Duration::from_secs(5))
-        .flow_control(FlowControl::None)
-        .open_native_async()
-        .expect("Failed to connect to robot!");
+    let (system_port, communications_port) = find_and_open_ports(
+        port.clone(),
+        controller,
+        user_port,
+        baud_rate,
+        timeouts.connect,
+    )
+    .await?;
 
-    let user_port = tokio_serial::new(user, 115200)
+    Ok(SerialPortConnection {
+        system_port,
+        communications_port,
+        port,
+        baud_rate,
+        controller,
+        user_port,
+        auto_reconnect,
+        timeouts,
+    })
+}
+
+/// Finds and opens the brain's ports, trying again until `timeout` passes, such as while the brain
+/// is still starting up after being plugged in.
+async fn find_and_open_ports(
+    port: Option<String>,
+    controller: bool,
+    user_port: bool,
+    baud_rate: u32,
+    timeout: Duration,
+) -> Result<(SerialStream, Option<SerialStream>), ConnectionError> {
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 1;
+    loop {
+        match find_ports(port.clone(), controller, user_port)
+            .and_then(|(system, user)| open_ports(system, user, baud_rate))
+        {
+            Ok(ports) => return Ok(ports),
+            Err(err) if Instant::now() + RECONNECT_DELAY < deadline => {
+                debug!(
+                    "attempt {} to open the brain's ports failed: {}",
+                    attempt, err
+                );
+            }
+            Err(err) => return Err(err),
+        }
+        attempt += 1;
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+fn open_ports(
+    system: String,
+    user: Option<String>,
+    baud_rate: u32,
+) -> Result<(SerialStream, Option<SerialStream>), ConnectionError> {
+    let system_port = tokio_serial::new(system, baud_rate)
         .parity(Parity::None)
         .data_bits(DataBits::Eight)
-        .timeout(Duration::from_secs(5))
+        .timeout(PORT_TIMEOUT)
         .flow_control(FlowControl::None)
-        .open_native_async()
-        .expect("Failed to connect to robot!");
+        .open_native_async()?;
 
-    Ok(SerialPortConnection {
-        system_port,
-        communications_port: Some(user_port),
-    })
+    // the system port is all that is needed to talk to the brain, so a user port that cannot be
+    // opened only disables user serial
+    let user_port = user.and_then(|user| {
+        tokio_serial::new(&user, baud_rate)
+            .parity(Parity::None)
+            .data_bits(DataBits::Eight)
+            .timeout(PORT_TIMEOUT)
+            .flow_control(FlowControl::None)
+            .open_native_async()
+            .inspect_err(|err| {
+                warn!(
+                    "failed to open user port {} ({}), user serial will not be available",
+                    user, err
+                )
+            })
+            .ok()
+    });
+
+    Ok((system_port, user_port))
 }
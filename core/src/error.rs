@@ -18,6 +18,10 @@ pub enum ConnectionError {
     IoError(#[from] std::io::Error),
     #[error("invalid PIN")]
     InvalidPIN,
+    #[error("`{0}` is not a valid MAC address")]
+    InvalidMacAddress(String),
+    #[error("the daemon has reached its client limit")]
+    DaemonFull,
 }
 
 #[derive(Error, Debug)]
@@ -32,6 +36,12 @@ pub enum CommunicationError {
     TimedOut,
     #[error("disconnected")]
     Eof,
+    #[error("no user serial available on this connection")]
+    UserSerialUnavailable,
+    #[error("`{0}` is too long (at most {1} characters)")]
+    NameTooLong(String, usize),
+    #[error("region of {2}x{3} at ({0}, {1}) does not fit within the screen")]
+    InvalidScreenRegion(u16, u16, u16, u16),
 }
 
 #[derive(Error, Debug)]
@@ -48,6 +58,8 @@ pub enum CommandError {
     IoError(#[from] std::io::Error),
     #[error("communications parsing error: {0}")]
     ParseError(#[from] ParseError),
+    #[error("slot {0} is occupied, pass --overwrite to replace it")]
+    SlotOccupied(u8),
 }
 
 #[derive(Error, Debug)]
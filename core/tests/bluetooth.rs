@@ -0,0 +1,43 @@
+use v5_serial::connection::{max_packet_size_for_mtu, parse_mac_address, parse_pin};
+
+#[test]
+fn mac_address_formats() {
+    let expected = parse_mac_address("AA:BB:CC:DD:EE:0F").unwrap();
+    assert_eq!(expected.to_string(), "AA:BB:CC:DD:EE:0F");
+    for address in [
+        "aa:bb:cc:dd:ee:0f",
+        "AA-BB-CC-DD-EE-0F",
+        "aabbccddee0f",
+        "aabb.ccdd.ee0f",
+        " AA:BB:CC:DD:EE:0F\n",
+    ] {
+        assert_eq!(parse_mac_address(address).unwrap(), expected, "{}", address);
+    }
+}
+
+#[test]
+fn invalid_mac_addresses() {
+    for address in [
+        "",
+        "AA:BB:CC:DD:EE",
+        "AA:BB:CC:DD:EE:FF:00",
+        "GG:BB:CC:DD:EE:FF",
+        "aabbccddeeé",
+    ] {
+        assert!(parse_mac_address(address).is_err(), "{}", address);
+    }
+}
+
+#[test]
+fn pins() {
+    assert_eq!(parse_pin(" 1234\n").unwrap(), [1, 2, 3, 4]);
+    assert!(parse_pin("123").is_err());
+    assert!(parse_pin("12a4").is_err());
+}
+
+#[test]
+fn packet_size_from_mtu() {
+    assert_eq!(max_packet_size_for_mtu(None), 244);
+    assert_eq!(max_packet_size_for_mtu(Some(517)), 514);
+    assert_eq!(max_packet_size_for_mtu(Some(23)), 20);
+}
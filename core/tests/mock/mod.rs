@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use v5_serial::brain::Brain;
+use v5_serial::buffer::ReceivingBuffer;
+use v5_serial::connection::RobotConnection;
+use v5_serial::error::CommunicationError;
+
+/// A fake brain that records every packet sent to it and replies with canned response payloads.
+pub struct MockConnection {
+    sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    responses: VecDeque<Vec<u8>>,
+}
+
+impl MockConnection {
+    /// Creates a brain backed by a mock connection, along with a handle to the packets it receives.
+    /// Each response is the payload that follows the acknowledgement byte.
+    pub fn brain(responses: Vec<Vec<u8>>) -> (Brain, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let connection = Self {
+            sent: sent.clone(),
+            responses: responses.into(),
+        };
+        (Brain::new(Box::new(connection)), sent)
+    }
+}
+
+#[async_trait::async_trait]
+impl RobotConnection for MockConnection {
+    fn get_max_packet_size(&self) -> u16 {
+        244
+    }
+
+    async fn send_packet(&mut self, data: &[u8]) -> Result<ReceivingBuffer, CommunicationError> {
+        self.sent.lock().unwrap().push(data.to_vec());
+        let response = self
+            .responses
+            .pop_front()
+            .ok_or(CommunicationError::TimedOut)?;
+        Ok(ReceivingBuffer::new(response.into_boxed_slice(), 0))
+    }
+
+    async fn write_serial(&mut self, data: &[u8]) -> Result<usize, CommunicationError> {
+        Ok(data.len())
+    }
+
+    async fn read_serial(&mut self, _data: &mut [u8]) -> Result<usize, CommunicationError> {
+        Ok(0)
+    }
+
+    async fn reset(&mut self) -> Result<(), CommunicationError> {
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<(), CommunicationError> {
+        Ok(())
+    }
+}
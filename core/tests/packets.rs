@@ -0,0 +1,346 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use v5_serial::brain::Brain;
+use v5_serial::brain::filesystem::{
+    transfer_chunk_size, DeleteFlags, FileFlags, FileTransfer, FileType, FileVersion,
+    TransferDirection, TransferTarget, Vid, CRC32,
+};
+use v5_serial::brain::system::{ExecutionFlags, KernelVariable};
+use v5_serial::error::CommunicationError;
+
+use crate::mock::MockConnection;
+
+mod mock;
+
+const JAN_01_2000: Duration = Duration::from_secs(946684800);
+
+async fn start_upload(brain: &mut Brain) -> FileTransfer<'_> {
+    brain
+        .file_transfer_initialize(
+            TransferDirection::Upload,
+            TransferTarget::Flash,
+            Vid::User,
+            true,
+            0x100,
+            0x07800000,
+            0x12345678,
+            0,
+            FileType::Bin,
+            "slot_1.bin",
+            UNIX_EPOCH + JAN_01_2000,
+        )
+        .await
+        .unwrap()
+}
+
+fn upload_response() -> Vec<u8> {
+    let mut response = Vec::new();
+    response.extend_from_slice(&244_u16.to_le_bytes());
+    response.extend_from_slice(&0x100_u32.to_le_bytes());
+    response.extend_from_slice(&0x12345678_u32.to_le_bytes());
+    response
+}
+
+#[tokio::test]
+async fn get_system_status() {
+    let mut response = vec![0_u8; 21];
+    response[16] = 1;
+    response[17..21].copy_from_slice(&0xDEADBEEF_u32.to_le_bytes());
+    let (mut brain, sent) = MockConnection::brain(vec![response]);
+
+    let status = brain.get_system_status().await.unwrap();
+
+    assert_eq!(status.touch.to_string(), "1.0.0-0");
+    assert_eq!(status.system_id, 0xDEADBEEF);
+    assert_eq!(
+        sent.lock().unwrap()[0],
+        [0xc9, 0x36, 0xb8, 0x47, 0x56, 0x22, 0x00, 0x60, 0xfc]
+    );
+}
+
+#[tokio::test]
+async fn get_bootloader_version() {
+    let mut response = vec![0_u8; 21];
+    response.extend_from_slice(&[0; 8]);
+    response.extend_from_slice(&[1, 2, 3, 4]);
+    response.extend_from_slice(&[0; 2]);
+    let (mut brain, _sent) = MockConnection::brain(vec![response, vec![0_u8; 21]]);
+
+    let version = brain.get_bootloader_version().await.unwrap();
+    assert_eq!(version.unwrap().to_string(), "1.2.3-4");
+    assert!(brain.get_bootloader_version().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn set_kernel_variable() {
+    let (mut brain, sent) = MockConnection::brain(vec![vec![]]);
+
+    brain
+        .set_kernel_variable(KernelVariable::TeamNumber, "1234A")
+        .await
+        .unwrap();
+
+    let mut expected = vec![0xc9, 0x36, 0xb8, 0x47, 0x56, 0x2f, 0x11];
+    expected.extend_from_slice(b"teamnumber\0");
+    expected.extend_from_slice(b"1234A\0");
+    expected.extend_from_slice(&[0x22, 0x18]);
+    assert_eq!(sent.lock().unwrap()[0], expected);
+}
+
+#[tokio::test]
+async fn file_transfer_initialize() {
+    let (mut brain, sent) = MockConnection::brain(vec![upload_response()]);
+
+    let transfer = start_upload(&mut brain).await;
+    assert_eq!(transfer.parameters.max_packet_size, 244);
+    assert_eq!(transfer.parameters.file_size, 0x100);
+    assert_eq!(transfer.parameters.crc, 0x12345678);
+
+    let mut expected = vec![0xc9, 0x36, 0xb8, 0x47, 0x56, 0x11, 0x34];
+    expected.extend_from_slice(&[0x01, 0x01, 0x01, 0x01]);
+    expected.extend_from_slice(&[0x00, 0x01, 0x00, 0x00]);
+    expected.extend_from_slice(&[0x00, 0x00, 0x80, 0x07]);
+    expected.extend_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+    expected.extend_from_slice(b"bin\0");
+    expected.extend_from_slice(&[0; 8]);
+    expected.extend_from_slice(b"slot_1.bin");
+    expected.extend_from_slice(&[0; 14]);
+    expected.extend_from_slice(&[0xdb, 0x7f]);
+    assert_eq!(sent.lock().unwrap()[0], expected);
+}
+
+#[tokio::test]
+async fn unaligned_write_is_padded() {
+    let (mut brain, sent) = MockConnection::brain(vec![upload_response(), vec![]]);
+
+    let mut transfer = start_upload(&mut brain).await;
+    transfer.write(&[1, 2, 3, 4, 5], 0x07800000).await.unwrap();
+
+    assert_eq!(
+        sent.lock().unwrap()[1],
+        [
+            0xc9, 0x36, 0xb8, 0x47, 0x56, 0x13, 0x0c, 0x00, 0x00, 0x80, 0x07, 0x01, 0x02, 0x03,
+            0x04, 0x05, 0x00, 0x00, 0x00, 0xd5, 0xb3
+        ]
+    );
+}
+
+#[tokio::test]
+async fn extended_length() {
+    let (mut brain, sent) = MockConnection::brain(vec![upload_response(), vec![]]);
+    let data: Vec<u8> = (0..125).collect();
+
+    let mut transfer = start_upload(&mut brain).await;
+    transfer.write(&data, 0x07800000).await.unwrap();
+
+    let sent = sent.lock().unwrap();
+    let packet = &sent[1];
+    // 132 bytes of content (address, data and padding) needs the two byte length encoding
+    assert_eq!(
+        packet[..8],
+        [0xc9, 0x36, 0xb8, 0x47, 0x56, 0x13, 0x80, 0x84]
+    );
+    assert_eq!(packet.len(), 8 + 132 + 2);
+    assert_eq!(packet[8..12], [0x00, 0x00, 0x80, 0x07]);
+    assert_eq!(packet[12..137], data[..]);
+    assert_eq!(packet[137..140], [0, 0, 0]);
+    assert_eq!(packet[140..], [0xa7, 0x82]);
+}
+
+#[test]
+fn chunk_size_accounts_for_overhead_and_alignment() {
+    // limited by the brain, which only allows half of what it reports
+    assert_eq!(transfer_chunk_size(4096, 244), 108);
+    assert_eq!(transfer_chunk_size(4096, 2048), 1008);
+    // limited by the connection
+    assert_eq!(transfer_chunk_size(244, 4096), 228);
+    assert_eq!(transfer_chunk_size(100, 4096), 84);
+    // never smaller than a single aligned word
+    assert_eq!(transfer_chunk_size(16, 4096), 4);
+}
+
+#[tokio::test]
+async fn upload_chunk_size_uses_negotiated_size() {
+    let (mut brain, _) = MockConnection::brain(vec![upload_response()]);
+
+    let transfer = start_upload(&mut brain).await;
+
+    assert_eq!(transfer.chunk_size(), 108);
+}
+
+#[tokio::test]
+async fn abandoned_transfers_are_closed_before_the_next() {
+    let (mut brain, sent) =
+        MockConnection::brain(vec![upload_response(), vec![], upload_response()]);
+
+    drop(start_upload(&mut brain).await);
+    start_upload(&mut brain).await;
+
+    let sent = sent.lock().unwrap();
+    let commands: Vec<u8> = sent.iter().map(|packet| packet[5]).collect();
+    assert_eq!(commands, [0x11, 0x12, 0x11]);
+    // closed without running anything
+    assert_eq!(sent[1][7], 0);
+}
+
+#[tokio::test]
+async fn write_length_matches_padded_body() {
+    // the final chunk of a file can end anywhere, but the declared length has to cover the
+    // padding the body is written with
+    for len in 1..=8 {
+        let (mut brain, sent) = MockConnection::brain(vec![upload_response(), vec![]]);
+
+        let mut transfer = start_upload(&mut brain).await;
+        transfer.write(&[0xAB; 8][..len], 0x07800000).await.unwrap();
+
+        let packet = &sent.lock().unwrap()[1];
+        let body = &packet[7..packet.len() - 2];
+        assert_eq!(packet[6] as usize, body.len());
+        assert_eq!(body.len(), 4 + len.next_multiple_of(4));
+        assert!(body[4 + len..].iter().all(|byte| *byte == 0));
+    }
+}
+
+#[tokio::test]
+async fn reinitialize_resends_the_initialization() {
+    let mut second = upload_response();
+    second[..2].copy_from_slice(&128_u16.to_le_bytes());
+    let (mut brain, sent) = MockConnection::brain(vec![upload_response(), second]);
+
+    let mut transfer = start_upload(&mut brain).await;
+    transfer.reinitialize().await.unwrap();
+    assert_eq!(transfer.parameters.max_packet_size, 128);
+    drop(transfer);
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0], sent[1]);
+}
+
+#[tokio::test]
+async fn delete_file_is_a_single_packet() {
+    let (mut brain, sent) = MockConnection::brain(vec![vec![]]);
+
+    brain
+        .delete_file(Vid::User, DeleteFlags::empty(), "slot_1.bin")
+        .await
+        .unwrap();
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0][..9],
+        [0xc9, 0x36, 0xb8, 0x47, 0x56, 0x1b, 0x1a, 0x01, 0x00]
+    );
+    assert_eq!(&sent[0][9..19], b"slot_1.bin");
+}
+
+fn metadata_response(vid: u8, name: &str) -> Vec<u8> {
+    let mut response = vec![0_u8; 49];
+    response[0] = vid;
+    response[13..16].copy_from_slice(b"bin");
+    response[25..25 + name.len()].copy_from_slice(name.as_bytes());
+    response
+}
+
+#[tokio::test]
+async fn list_files_only_returns_the_requested_vid() {
+    let (mut brain, _) = MockConnection::brain(vec![
+        2_u16.to_le_bytes().to_vec(),
+        metadata_response(1, "slot_1.bin"),
+        metadata_response(24, "cold.bin"),
+    ]);
+
+    let files = brain
+        .list_files(Vid::User, FileFlags::empty())
+        .await
+        .unwrap();
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "slot_1.bin");
+}
+
+#[tokio::test]
+async fn file_version_is_decoded() {
+    let mut response = metadata_response(1, "slot_1.bin");
+    response[21..25].copy_from_slice(&0x01020300_u32.to_le_bytes());
+    let (mut brain, _) = MockConnection::brain(vec![response]);
+
+    let metadata = brain
+        .get_file_metadata_by_name(Vid::User, FileFlags::empty(), "slot_1.bin")
+        .await
+        .unwrap();
+
+    assert_eq!(metadata.version, 0x01020300);
+    assert_eq!(metadata.file_version().to_string(), "1.2.3");
+    assert_eq!(FileVersion::from(16777216).to_string(), "1.0.0");
+    assert_eq!(FileVersion::from(0x01000004).to_string(), "1.0.0-4");
+}
+
+#[tokio::test]
+async fn mismatched_downloads_are_read_again() {
+    let data = [1_u8, 2, 3, 4, 5, 6, 7, 8];
+    let mut metadata = metadata_response(1, "slot_1.bin");
+    metadata[1..5].copy_from_slice(&8_u32.to_le_bytes());
+    metadata[9..13].copy_from_slice(&CRC32.checksum(&data).to_le_bytes());
+    let mut init = Vec::new();
+    init.extend_from_slice(&244_u16.to_le_bytes());
+    init.extend_from_slice(&8_u32.to_le_bytes());
+    init.extend_from_slice(&0_u32.to_le_bytes());
+    let (mut brain, sent) = MockConnection::brain(vec![
+        metadata,
+        init.clone(),
+        vec![1, 2, 3, 4, 1, 2, 3, 4],
+        vec![],
+        init,
+        data.to_vec(),
+        vec![],
+    ]);
+
+    let download = brain.read_file(Vid::User, "slot_1.bin").await.unwrap();
+
+    assert!(download.crc_matches);
+    assert_eq!(download.attempts, 2);
+    assert_eq!(download.data, data);
+    assert_eq!(sent.lock().unwrap().len(), 7);
+}
+
+#[tokio::test]
+async fn timed_out_packets_are_retried() {
+    let (mut brain, sent) = MockConnection::brain(vec![]);
+
+    assert!(brain.get_system_status().await.is_err());
+    assert_eq!(sent.lock().unwrap().len(), 4);
+    let stats = brain.stats();
+    assert_eq!(stats.retransmissions, 3);
+    assert_eq!(stats.round_trips, 0);
+    assert_eq!(stats.bytes_sent, 4 * 9);
+}
+
+#[tokio::test]
+async fn long_names_are_rejected() {
+    let (mut brain, sent) = MockConnection::brain(vec![]);
+
+    let result = brain
+        .execute_program(Vid::User, ExecutionFlags::empty(), &"a".repeat(24))
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(CommunicationError::NameTooLong(_, 23))
+    ));
+    assert!(sent.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn out_of_range_screen_regions_are_rejected() {
+    let (mut brain, sent) = MockConnection::brain(vec![]);
+
+    let result = brain.read_screen(400, 0, 100, 10).await;
+
+    assert!(matches!(
+        result,
+        Err(CommunicationError::InvalidScreenRegion(400, 0, 100, 10))
+    ));
+    assert!(sent.lock().unwrap().is_empty());
+}
@@ -0,0 +1,51 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use v5_serial::brain::system::{convert_from_vex_timestamp, convert_to_vex_timestamp};
+
+const JAN_01_2000: u64 = 946684800;
+
+#[test]
+fn epoch_is_jan_01_2000() {
+    let epoch = UNIX_EPOCH + Duration::from_secs(JAN_01_2000);
+    assert_eq!(convert_to_vex_timestamp(epoch), 0);
+    assert_eq!(convert_from_vex_timestamp(0), epoch);
+}
+
+#[test]
+fn known_dates() {
+    // 2024-01-01T00:00:00Z
+    let date = UNIX_EPOCH + Duration::from_secs(1704067200);
+    assert_eq!(convert_to_vex_timestamp(date), 757382400);
+    assert_eq!(convert_from_vex_timestamp(757382400), date);
+
+    // 2036-02-07T06:28:16Z, the end of 32 bit NTP time
+    let date = UNIX_EPOCH + Duration::from_secs(2085978496);
+    assert_eq!(convert_to_vex_timestamp(date), 1139293696);
+}
+
+#[test]
+fn round_trip() {
+    for timestamp in [0, 1, 86399, 86400, 757382400, 1139293696, u32::MAX] {
+        assert_eq!(
+            convert_to_vex_timestamp(convert_from_vex_timestamp(timestamp)),
+            timestamp
+        );
+    }
+}
+
+#[test]
+fn sub_second_precision_is_truncated() {
+    let date = UNIX_EPOCH + Duration::from_secs(JAN_01_2000 + 10) + Duration::from_millis(999);
+    assert_eq!(convert_to_vex_timestamp(date), 10);
+}
+
+#[test]
+fn out_of_range_times_are_clamped() {
+    assert_eq!(convert_to_vex_timestamp(UNIX_EPOCH), 0);
+    assert_eq!(
+        convert_to_vex_timestamp(UNIX_EPOCH + Duration::from_secs(JAN_01_2000 - 1)),
+        0
+    );
+    let after_2136 = UNIX_EPOCH + Duration::from_secs(JAN_01_2000 + u32::MAX as u64 + 1);
+    assert_eq!(convert_to_vex_timestamp(after_2136), u32::MAX);
+}